@@ -1,73 +1,66 @@
+mod atomic;
+pub mod blocking;
+mod cache;
+mod deezer;
+mod embedded;
+mod extract;
+mod http;
+mod itunes;
+mod lastfm;
+mod local;
+mod matching;
+mod musicbrainz;
+mod overrides;
+pub mod pending;
+mod provider;
+pub mod seed;
+#[cfg(test)]
+mod test_server;
+mod upload;
+
+use crate::config::{AlbumArtConfig, ArtProviderKind, PendingQueueConfig, data_dir};
 use crate::mpd_conn::try_get_first_tag;
+use cache::{ArtCache, CachedArt};
+use deezer::DeezerProvider;
+use http::RetryPolicy;
+use itunes::ItunesProvider;
+use lastfm::LastfmProvider;
+use local::LocalProvider;
+use mpd_client::Client as MpdClient;
 use mpd_client::responses::Song;
 use mpd_client::tag::Tag;
+use musicbrainz::{MusicBrainzProvider, Type};
+use overrides::{OverrideArt, Overrides};
+use pending::{PendingEntry, QueuedEntry};
+use provider::{ArtLookup, ArtProvider};
 use reqwest::Client;
 use reqwest::header::{HeaderMap, HeaderValue};
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::fmt::{Display, Formatter};
-use std::path::Path;
 use std::fs;
-use std::process::{Command, Stdio};
-use chrono::Utc;
-use serde_json::json;
-
-const MUSIC_ROOT: &str = "/mnt/main/Music"; 
-const PENDING_MB_QUEUE_DIR: &str = "/home/Yaya/.local/share/mpd-rpc/pending_musicbrainz";
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use upload::Uploader;
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "kebab-case")]
-struct SearchResult {
-    release_groups: Vec<ReleaseGroup>,
-}
-
-#[derive(Deserialize, Debug)]
-struct ReleaseGroup {
-    id: String,
-}
-
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "kebab-case")]
-struct Release {
-    id: String,
-    release_group: ReleaseGroup,
-    cover_art_archive: ReleaseCoverArt,
-}
-
-#[derive(Deserialize, Debug)]
-struct ReleaseCoverArt {
-    front: bool,
-}
-
-#[derive(Debug, Copy, Clone)]
-enum Type {
-    Release,
-    ReleaseGroup,
-}
-
-impl Display for Type {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                Self::Release => "release",
-                Self::ReleaseGroup => "release-group",
-            }
-        )
-    }
-}
-
 pub struct AlbumArtClient {
-    release_group_cache: HashMap<(String, String), (String, Type)>,
-    client: Client,
+    cache: ArtCache,
+    overrides: Overrides,
+    /// Used to find covers for overrides, whether or not it is a configured provider.
+    musicbrainz: MusicBrainzProvider,
+    providers: Vec<Box<dyn ArtProvider>>,
+    uploader: Option<Uploader>,
+    pending_queue_dir: PathBuf,
+    embedded_fallback: bool,
 }
 
 impl AlbumArtClient {
-    pub fn new() -> Self {
-        let release_group_cache = HashMap::new();
+    pub fn new(config: &AlbumArtConfig, pending_queue: &PendingQueueConfig) -> Self {
+        let cache = ArtCache::load(
+            config.cache_file.clone(),
+            Duration::from_secs(config.cache_ttl_secs),
+            Duration::from_secs(config.negative_cache_ttl_secs),
+        );
 
         let mut header_map = HeaderMap::new();
         header_map.insert(
@@ -78,59 +71,43 @@ impl AlbumArtClient {
         let client = Client::builder()
             .user_agent(APP_USER_AGENT)
             .default_headers(header_map)
+            .timeout(Duration::from_secs(config.request_timeout_secs))
             .build()
             .expect("Failed to create HTTP client");
 
-        Self {
-            release_group_cache,
-            client,
-        }
-    }
+        let retry_policy = RetryPolicy {
+            max_retries: config.max_retries,
+            base_delay: Duration::from_secs(1),
+        };
 
-    /// Looks up a release by its UUID on MusicBrainz.
-    /// If the release has a cover, returns the ID of that record.
-    /// If not, returns the ID of its release group.
-    async fn get_record_id(&self, release_id: &str) -> Option<(String, Type)> {
-        let url = format!("https://musicbrainz.org/ws/2/release/{release_id}?inc=release-groups");
-
-        let response = self.client.get(&url).send().await;
-
-        match response {
-            Ok(response) if response.status() == 200 => {
-                let response = response.json::<Release>().await;
-                response.ok().map(|release| {
-                    if release.cover_art_archive.front {
-                        (release.id, Type::Release)
-                    } else {
-                        (release.release_group.id, Type::ReleaseGroup)
-                    }
-                })
-            }
-            _ => None,
-        }
-    }
-
-    /// Searches for a release on MusicBrainz
-    /// Returns its ID if one is found.
-    async fn find_release_group_id(&self, artist: &str, album: &str) -> Option<String> {
-        let query = format!("artist:{artist} AND release:{album}");
-        let url = format!("https://musicbrainz.org/ws/2/release-group/?query={query}&limit=1");
+        let musicbrainz = MusicBrainzProvider::new(
+            client.clone(),
+            retry_policy,
+            config.min_match_score,
+            &config.musicbrainz,
+        );
 
-        let response = self.client.get(&url).send().await;
+        let providers = create_providers(config, &client, retry_policy, &musicbrainz);
 
-        if let Ok(response) = response {
-            if response.status() != 200 {
-                return None;
-            }
+        let uploader = config.upload.clone().map(|target| {
+            Uploader::new(
+                client.clone(),
+                retry_policy,
+                target,
+                data_dir().join("uploads.json"),
+            )
+        });
 
-            let mut response = response
-                .json::<SearchResult>()
-                .await
-                .expect("Received response from MusicBrainz in unexpected format");
+        pending::migrate(&pending_queue.dir);
 
-            response.release_groups.pop().map(|rg| rg.id)
-        } else {
-            None
+        Self {
+            cache,
+            overrides: Overrides::load(config.overrides_file.clone()),
+            musicbrainz,
+            providers,
+            uploader,
+            pending_queue_dir: pending_queue.dir.clone(),
+            embedded_fallback: config.embedded_fallback,
         }
     }
 
@@ -146,152 +123,418 @@ impl AlbumArtClient {
         }
     }
 
+    /// Gets the URL to an album's front cover if it is already known,
+    /// from the overrides or cache, without making any requests.
+    ///
+    /// Returns `None` if the cover needs looking up,
+    /// or `Some(None)` if the album is known to have no cover.
+    pub fn get_cached_album_art_url(&mut self, song: &Song) -> Option<Option<String>> {
+        let cache_key = Self::get_cache_key(song)?;
+        let (artist, album) = &cache_key;
+
+        if let Some(art) = self.overrides.get(song, artist, album) {
+            return match art {
+                OverrideArt::Record(_, id) => self.overrides.get_resolved(&id),
+                OverrideArt::Url(url) => Some(Some(url)),
+                OverrideArt::NoArt => Some(None),
+            };
+        }
+
+        self.cache.get(&cache_key).map(|art| match art {
+            CachedArt::Found { url, .. } => Some(url.clone()),
+            CachedArt::Missing { .. } => None,
+        })
+    }
+
+    /// Adds `song` to its release's entry in the pending queue,
+    /// if the release is already queued.
+    ///
+    /// Lookups answered from the cache do this themselves,
+    /// but it needs doing separately for songs whose cover is already known.
+    pub async fn record_pending_track(&self, song: &Song, music_dir: &Path) {
+        pending::record_track(song, music_dir, &self.pending_queue_dir).await;
+    }
+
     /// Attempts to get the URL to the current album's front cover
-    /// by fetching it from MusicBrainz / Cover Art Archive.
+    /// by asking each configured provider in turn.
+    /// Results, including failures, are cached on disk.
+    /// A matching entry in the overrides file takes precedence over both.
     ///
-    /// Uses MPD's internal MusicBrainz album ID tag if it's set,
-    /// otherwise falls back to searching.
-    pub async fn get_album_art_url(&mut self, song: Song) -> Option<String> {
-        let cache_key = Self::get_cache_key(&song);
-
-        if let Some(cache_key) = cache_key {
-            let id = if let Some(id) = self.release_group_cache.remove(&cache_key) {
-                Some(id)
-            } else {
-                let release_id = try_get_first_tag(song.tags.get(&Tag::MusicBrainzReleaseId));
-                if let Some(release_id) = release_id {
-                    self.get_record_id(release_id).await
-                } else {
-                    self.find_release_group_id(&cache_key.0, &cache_key.1)
-                        .await
-                        .map(|id| (id, Type::ReleaseGroup))
+    /// If MusicBrainz reports the album as missing,
+    /// it is added to the pending queue.
+    /// If an uploader is configured and no provider has a cover,
+    /// the cover saved to the queue is uploaded and used instead.
+    /// The cover is read from `mpd` when queueing.
+    /// `music_dir` is the directory the song's URL is relative to,
+    /// used to locate the file for reading embedded covers.
+    pub async fn get_album_art_url(
+        &mut self,
+        song: Song,
+        music_dir: &Path,
+        mpd: &MpdClient,
+    ) -> Option<String> {
+        let cache_key = Self::get_cache_key(&song)?;
+        let (artist, album) = &cache_key;
+
+        if let Some(art) = self.overrides.get(&song, artist, album) {
+            debug!("Using override for {cache_key:?}: {art:?}");
+            return match art {
+                OverrideArt::Record(record_type, id) => {
+                    self.get_override_record_art(record_type, id).await
                 }
+                OverrideArt::Url(url) => Some(url),
+                OverrideArt::NoArt => None,
             };
+        }
+
+        if let Some(art) = self.cache.get(&cache_key) {
+            debug!("Using cached album art for {cache_key:?}: {art:?}");
+            self.record_pending_track(&song, music_dir).await;
 
-            if let Some((id, record_type)) = id {
-                let url = format!(
-                    "https://coverartarchive.org/{record_type}/{id}/front-250"
-                );
-
-                self.release_group_cache
-                    .insert(cache_key, (id.clone(), record_type));
-
-                let exists = self
-                    .client
-                    .head(&url)
-                    .send()
-                    .await
-                    .map(|resp| resp.status().is_success())
-                    .unwrap_or(false);
-
-                if exists {
-                    Some(url)
-                } else {
-                    let mbid_opt = try_get_first_tag(song.tags.get(&Tag::MusicBrainzReleaseId));
-                    queue_missing_mb_entry(&song, mbid_opt, "missing_caa");
-                    None
+            return match art {
+                CachedArt::Found { url, .. } => Some(url.clone()),
+                CachedArt::Missing { .. } => None,
+            };
+        }
+
+        let mut found = None;
+        let mut missing = None;
+        let mut local_cover = None;
+        let mut failed = false;
+
+        for provider in &self.providers {
+            match provider.find_art(&song, music_dir, artist, album).await {
+                Ok(ArtLookup::Found { url, record }) => {
+                    debug!("Found art for {cache_key:?} via {}", provider.name());
+                    found = Some((url, provider.name(), record));
+                    break;
+                }
+                Ok(ArtLookup::Local(path)) => {
+                    debug!("Found local art for {cache_key:?} via {}", provider.name());
+
+                    if let Some(uploader) = &mut self.uploader {
+                        match upload_cover(uploader, &path).await {
+                            Ok(Some(url)) => {
+                                found = Some((url, provider.name(), None));
+                                break;
+                            }
+                            Ok(None) => {}
+                            Err(err) => {
+                                warn!("Failed to upload cover for {cache_key:?}: {err}");
+                                failed = true;
+                            }
+                        }
+                    }
+
+                    local_cover.get_or_insert(path);
+                }
+                Ok(ArtLookup::NotFound(release)) => {
+                    debug!("No art for {cache_key:?} via {}", provider.name());
+                    missing = release.or(missing);
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to look up {cache_key:?} via {}: {err}",
+                        provider.name()
+                    );
+                    failed = true;
+                }
+            }
+        }
+
+        let queued_cover = match &missing {
+            Some(missing) => {
+                pending::queue_release(
+                    mpd,
+                    &song,
+                    missing,
+                    local_cover.as_deref(),
+                    music_dir,
+                    &self.pending_queue_dir,
+                    self.embedded_fallback,
+                )
+                .await
+            }
+            None => None,
+        };
+
+        if let (None, Some(uploader), Some(path)) = (&found, &mut self.uploader, queued_cover) {
+            match upload_cover(uploader, &path).await {
+                Ok(Some(url)) => found = Some((url, "upload", None)),
+                Ok(None) => {}
+                Err(err) => {
+                    warn!("Failed to upload cover for {cache_key:?}: {err}");
+                    failed = true;
                 }
-            } else {
-                queue_missing_mb_entry(&song, None, "no_mb_match");
-                None
             }
-        } else {
-            None
         }
+
+        let art = match found {
+            Some((url, provider, record)) => found_art(url, provider, record),
+            // don't cache anything if a provider couldn't be reached,
+            // so the lookup is tried again next time
+            None if failed => return None,
+            None => CachedArt::Missing {
+                reason: missing
+                    .map_or("no_match", |missing| missing.reason)
+                    .to_string(),
+            },
+        };
+
+        let url = match &art {
+            CachedArt::Found { url, .. } => Some(url.clone()),
+            CachedArt::Missing { .. } => None,
+        };
+
+        self.cache.insert(cache_key, art).await;
+        url
     }
-}
 
-fn sanitize_for_filename(s: &str) -> String {
-    let mut out = String::new();
-    for c in s.chars() {
-        if c.is_ascii_alphanumeric() {
-            out.push(c);
-        } else if c.is_whitespace() || c == '-' || c == '_' {
-            out.push('_');
+    /// Looks up an album's cover ahead of time,
+    /// so `get_album_art_url` can answer from the cache.
+    ///
+    /// Unlike `get_album_art_url`, nothing is queued, recorded or uploaded.
+    /// Albums which would need any of those are left uncached,
+    /// to be handled in full once the song is played.
+    pub async fn prefetch_album_art(&mut self, song: &Song, music_dir: &Path) {
+        let Some(cache_key) = Self::get_cache_key(song) else {
+            return;
+        };
+        let (artist, album) = &cache_key;
+
+        if self.overrides.get(song, artist, album).is_some() || self.cache.get(&cache_key).is_some()
+        {
+            return;
+        }
+
+        for provider in &self.providers {
+            match provider.find_art(song, music_dir, artist, album).await {
+                Ok(ArtLookup::Found { url, record }) => {
+                    debug!("Prefetched art for {cache_key:?} via {}", provider.name());
+                    let art = found_art(url, provider.name(), record);
+                    self.cache.insert(cache_key, art).await;
+                    return;
+                }
+                // only used if there is an uploader
+                Ok(ArtLookup::Local(_)) if self.uploader.is_none() => {}
+                Ok(ArtLookup::NotFound(None)) => {}
+                Ok(ArtLookup::Local(_) | ArtLookup::NotFound(Some(_))) => {
+                    debug!("Not prefetching art for {cache_key:?}, it needs uploading or queueing");
+                    return;
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to prefetch {cache_key:?} via {}: {err}",
+                        provider.name()
+                    );
+                    return;
+                }
+            }
         }
+
+        debug!("No art to prefetch for {cache_key:?}");
+        let art = CachedArt::Missing {
+            reason: "no_match".to_string(),
+        };
+        self.cache.insert(cache_key, art).await;
     }
-    if out.is_empty() {
-        "unknown".to_string()
-    } else {
-        out
+
+    /// Gets a checker for pending queue entries
+    /// which can be used without holding on to the client.
+    pub fn pending_rechecker(&self) -> PendingRechecker {
+        PendingRechecker {
+            musicbrainz: self.musicbrainz.clone(),
+            pending_queue_dir: self.pending_queue_dir.clone(),
+        }
     }
-}
 
-/// Combined queue function:
-/// - reason = "missing_caa"  → MB release exists but CAA had no art
-/// - reason = "no_mb_match" → MB couldn't find any release
-fn queue_missing_mb_entry(song: &Song, mbid: Option<&str>, reason: &str) {
-    let base_dir = Path::new(PENDING_MB_QUEUE_DIR);
-    if let Err(e) = fs::create_dir_all(base_dir) {
-        eprintln!("failed to create pending MB queue dir: {e}");
-        return;
+    /// Drops an album's cached result, so it is looked up again next time it plays.
+    pub async fn remove_cached(&mut self, cache_key: &(String, String)) {
+        self.cache.remove(cache_key).await;
     }
 
-    let tags = &song.tags;
+    /// Gets the cover for an override's MusicBrainz record,
+    /// remembering it until the overrides file changes.
+    async fn get_override_record_art(&mut self, record_type: Type, id: String) -> Option<String> {
+        if let Some(url) = self.overrides.get_resolved(&id) {
+            return url;
+        }
 
-    let artist  = try_get_first_tag(tags.get(&Tag::Artist)).unwrap_or_default();
-    let album   = try_get_first_tag(tags.get(&Tag::Album)).unwrap_or_default();
-    let title   = try_get_first_tag(tags.get(&Tag::Title)).unwrap_or_default();
-    let trackno = try_get_first_tag(tags.get(&Tag::Track)).unwrap_or_default();
-    let date    = try_get_first_tag(tags.get(&Tag::Date)).unwrap_or_default();
+        match self.musicbrainz.find_record_art(&id, record_type).await {
+            Ok(url) => {
+                if url.is_none() {
+                    warn!("Overridden {record_type} {id} has no cover");
+                }
 
-    let rel_path = &song.url;
-    let audio_path = Path::new(MUSIC_ROOT).join(rel_path);
+                self.overrides.insert_resolved(id, url.clone());
+                url
+            }
+            Err(err) => {
+                warn!("Failed to look up overridden {record_type} {id}: {err}");
+                None
+            }
+        }
+    }
+}
 
-    let key = if let Some(mbid) = mbid {
-        mbid.to_string()
-    } else {
-        let a = sanitize_for_filename(&artist);
-        let t = sanitize_for_filename(&title);
-        format!("nombid_{a}_{t}")
-    };
+/// Re-checks entries in the pending queue against MusicBrainz.
+///
+/// Kept apart from [`AlbumArtClient`] so the client isn't locked
+/// while waiting on MusicBrainz, which would hold up lookups for the current song.
+pub struct PendingRechecker {
+    musicbrainz: MusicBrainzProvider,
+    pending_queue_dir: PathBuf,
+}
+
+impl PendingRechecker {
+    /// Checks whether a release in the pending queue
+    /// has since been added to MusicBrainz and Cover Art Archive.
+    ///
+    /// If it has, the entry is moved to the resolved directory
+    /// with the MBID and cover URL found,
+    /// and the album's cache key is returned,
+    /// for its cached result to be dropped so the cover is used next time it plays.
+    pub async fn recheck(&self, entry: &QueuedEntry) -> Option<(String, String)> {
+        let PendingEntry {
+            artist,
+            album,
+            mbid: release_id,
+            release_group_id,
+            ..
+        } = &entry.entry;
+
+        if artist.is_empty() || album.is_empty() {
+            debug!(
+                "Pending entry '{}' has no artist or album, skipping",
+                entry.key
+            );
+            return None;
+        }
 
-    let jpg_path = base_dir.join(format!("{key}.jpg"));
-    let json_path = base_dir.join(format!("{key}.json"));
+        let (mbid, url) = match self
+            .musicbrainz
+            .find_album_art(
+                release_group_id.as_deref(),
+                release_id.as_deref(),
+                artist,
+                album,
+            )
+            .await
+        {
+            Ok(Some(found)) => found,
+            Ok(None) => {
+                debug!("Pending entry '{}' still has no cover", entry.key);
+                return None;
+            }
+            Err(err) => {
+                warn!("Failed to re-check pending entry '{}': {err}", entry.key);
+                return None;
+            }
+        };
 
-    if jpg_path.exists() || json_path.exists() {
-        return;
-    }
+        let resolved = {
+            let (base_dir, key) = (self.pending_queue_dir.clone(), entry.key.clone());
+            let (mbid, url) = (mbid.clone(), url.clone());
 
-    let status = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-i")
-        .arg(&audio_path)
-        .arg("-an")
-        .arg("-vcodec")
-        .arg("copy")
-        .arg(&jpg_path)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status();
-
-    match status {
-        Ok(s) if s.success() && jpg_path.exists() => {
-        }
-        _ => {
-            let _ = fs::remove_file(&jpg_path);
+            blocking::run(move || pending::resolve(&base_dir, &key, Some(&mbid), Some(&url))).await
+        };
+
+        match resolved {
+            Ok(Some(_)) => {}
+            // removed from the queue since it was listed
+            Ok(None) => return None,
+            Err(err) => {
+                warn!("Failed to resolve pending entry '{}': {err}", entry.key);
+                return None;
+            }
         }
+
+        info!("Resolved pending entry '{}' to {mbid}", entry.key);
+        Some((artist.clone(), album.clone()))
     }
+}
 
-    let duration_secs = song
-        .duration
-        .map(|d| d.as_secs())
-        .unwrap_or(0);
-
-    let meta = json!({
-        "reason": reason,               
-        "mbid": mbid,                     
-        "artist": artist,
-        "album": album,
-        "title": title,
-        "trackno": trackno,
-        "date": date,
-        "duration_secs": duration_secs,
-        "source_path": audio_path.to_string_lossy(),
-        "added_at": Utc::now().to_rfc3339(),
-    });
-
-    if let Err(e) = fs::write(&json_path, serde_json::to_string_pretty(&meta).unwrap_or_default()) {
-        eprintln!("failed to write MB pending JSON: {e}");
+/// Builds the cache entry for a cover found by `provider`.
+fn found_art(url: String, provider: &str, record: Option<(String, Type)>) -> CachedArt {
+    let (record_id, record_type) = record.unzip();
+    CachedArt::Found {
+        url,
+        provider: provider.to_string(),
+        record_id,
+        record_type,
     }
 }
+
+/// Uploads a local cover, returning its public URL.
+///
+/// Returns `None` if the file can't be read.
+async fn upload_cover(uploader: &mut Uploader, path: &Path) -> http::Result<Option<String>> {
+    let image = {
+        let path = path.to_path_buf();
+        blocking::run(move || fs::read(path)).await
+    };
+
+    match image {
+        Ok(image) => uploader
+            .upload(image, upload::image_extension(path))
+            .await
+            .map(Some),
+        Err(err) => {
+            warn!("Failed to read cover '{}': {err}", path.display());
+            Ok(None)
+        }
+    }
+}
+
+/// Creates the providers listed in the config, in order.
+fn create_providers(
+    config: &AlbumArtConfig,
+    client: &Client,
+    retry_policy: RetryPolicy,
+    musicbrainz: &MusicBrainzProvider,
+) -> Vec<Box<dyn ArtProvider>> {
+    config
+        .providers
+        .iter()
+        .filter_map(|kind| -> Option<Box<dyn ArtProvider>> {
+            match kind {
+                ArtProviderKind::Musicbrainz => Some(Box::new(musicbrainz.clone())),
+                ArtProviderKind::Itunes => Some(Box::new(ItunesProvider::new(
+                    client.clone(),
+                    retry_policy,
+                    config.min_match_score,
+                    &config.itunes,
+                ))),
+                ArtProviderKind::Deezer => Some(Box::new(DeezerProvider::new(
+                    client.clone(),
+                    retry_policy,
+                    config.min_match_score,
+                    &config.deezer,
+                ))),
+                ArtProviderKind::Lastfm => {
+                    let provider = LastfmProvider::new(
+                        client.clone(),
+                        retry_policy,
+                        config.min_match_score,
+                        &config.lastfm,
+                    );
+
+                    if provider.is_none() {
+                        warn!("Last.fm provider enabled without an API key, skipping");
+                    }
+
+                    provider.map(|provider| Box::new(provider) as Box<dyn ArtProvider>)
+                }
+                ArtProviderKind::Local => {
+                    if config.upload.is_none() {
+                        warn!(
+                            "Local provider enabled without an uploader, its covers will only be used for the pending queue"
+                        );
+                    }
+
+                    Some(Box::new(LocalProvider::new(&config.local)))
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,80 @@
+//! Writes files via a temporary file, so readers never see one partly written.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of temporary files created so far, to keep their names unique.
+static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `contents` to `path`, replacing it in one step once fully written.
+/// The parent directory is created if needed.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp_path = write_temp(path, contents)?;
+
+    fs::rename(&tmp_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })
+}
+
+/// Writes `contents` to a temporary file next to `path`, returning the temporary file's path.
+///
+/// The name includes this process's ID and a count of files written,
+/// so concurrent writers, in this process or another, never share one.
+pub fn write_temp(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<PathBuf> {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let tmp_path = path.with_file_name(file_name);
+
+    fs::write(&tmp_path, contents).inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_path);
+    })?;
+
+    Ok(tmp_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_file_without_leaving_temporary_files() {
+        let dir = tempfile::tempdir().expect("temp dir to be created");
+        let path = dir.path().join("nested").join("file.json");
+
+        write(&path, "first").expect("file to be written");
+        write(&path, "second").expect("file to be replaced");
+
+        assert_eq!(
+            fs::read_to_string(&path).expect("file to be read"),
+            "second"
+        );
+
+        let files = fs::read_dir(path.parent().expect("parent dir"))
+            .expect("dir to be read")
+            .count();
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn temporary_files_are_unique() {
+        let dir = tempfile::tempdir().expect("temp dir to be created");
+        let path = dir.path().join("file.json");
+
+        let first = write_temp(&path, "first").expect("file to be written");
+        let second = write_temp(&path, "second").expect("file to be written");
+
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(first).expect("file to be read"), "first");
+    }
+}
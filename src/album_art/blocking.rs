@@ -0,0 +1,28 @@
+//! Runs blocking filesystem work, such as reading covers out of audio files,
+//! on Tokio's blocking thread pool so it can't hold up the async runtime.
+
+use tokio::sync::Semaphore;
+
+/// Most jobs to run at once.
+/// Reading covers can mean reading large audio files,
+/// so this stops a burst of lookups from saturating the disk.
+const MAX_JOBS: usize = 4;
+
+static PERMITS: Semaphore = Semaphore::const_new(MAX_JOBS);
+
+/// Runs `f` on the blocking thread pool,
+/// waiting for another job to finish first if `MAX_JOBS` are already running.
+pub async fn run<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let _permit = PERMITS
+        .acquire()
+        .await
+        .expect("semaphore never to be closed");
+
+    tokio::task::spawn_blocking(f)
+        .await
+        .expect("blocking job not to panic")
+}
@@ -0,0 +1,150 @@
+use super::musicbrainz::Type;
+use super::{atomic, blocking};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// The outcome of an album art lookup.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum CachedArt {
+    /// Art was found at `url` by `provider`.
+    /// The MusicBrainz record is set if the art came from Cover Art Archive.
+    Found {
+        url: String,
+        #[serde(default)]
+        provider: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        record_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        record_type: Option<Type>,
+    },
+    /// Negative entry: no art could be found.
+    /// `reason` matches the reason written to the pending queue.
+    Missing { reason: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    artist: String,
+    album: String,
+    #[serde(flatten)]
+    art: CachedArt,
+    /// Unix timestamp (seconds) the entry was stored at.
+    cached_at: i64,
+}
+
+impl CacheEntry {
+    fn is_expired(&self, ttl: Duration, negative_ttl: Duration) -> bool {
+        let ttl = match self.art {
+            CachedArt::Found { .. } => ttl,
+            CachedArt::Missing { .. } => negative_ttl,
+        };
+
+        let age = Utc::now().timestamp().saturating_sub(self.cached_at);
+        age < 0 || age as u64 >= ttl.as_secs()
+    }
+}
+
+/// Persistent album art lookup cache,
+/// keyed by `(artist, album)` and stored as JSON on disk.
+///
+/// Both successful and failed lookups are stored,
+/// each expiring after their own TTL.
+pub struct ArtCache {
+    path: PathBuf,
+    ttl: Duration,
+    negative_ttl: Duration,
+    entries: HashMap<(String, String), CacheEntry>,
+}
+
+impl ArtCache {
+    /// Loads the cache from `path`, dropping any expired entries.
+    /// A missing or unreadable file results in an empty cache.
+    pub fn load(path: PathBuf, ttl: Duration, negative_ttl: Duration) -> Self {
+        let entries = match read_entries(&path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                error!("Failed to read album art cache '{}': {err}", path.display());
+                Vec::new()
+            }
+        };
+
+        let entries = entries
+            .into_iter()
+            .filter(|entry| !entry.is_expired(ttl, negative_ttl))
+            .map(|entry| ((entry.artist.clone(), entry.album.clone()), entry))
+            .collect::<HashMap<_, _>>();
+
+        debug!("Loaded {} album art cache entries", entries.len());
+
+        Self {
+            path,
+            ttl,
+            negative_ttl,
+            entries,
+        }
+    }
+
+    /// Gets the cached result for an album, if there is one which has not expired.
+    pub fn get(&self, key: &(String, String)) -> Option<&CachedArt> {
+        self.entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(self.ttl, self.negative_ttl))
+            .map(|entry| &entry.art)
+    }
+
+    /// Stores the result for an album and writes the cache to disk.
+    pub async fn insert(&mut self, key: (String, String), art: CachedArt) {
+        let entry = CacheEntry {
+            artist: key.0.clone(),
+            album: key.1.clone(),
+            art,
+            cached_at: Utc::now().timestamp(),
+        };
+
+        self.entries.insert(key, entry);
+        self.save().await;
+    }
+
+    /// Removes the result for an album, if there is one, and writes the cache to disk.
+    pub async fn remove(&mut self, key: &(String, String)) {
+        if self.entries.remove(key).is_some() {
+            self.save().await;
+        }
+    }
+
+    async fn save(&self) {
+        let entries = self
+            .entries
+            .values()
+            .filter(|entry| !entry.is_expired(self.ttl, self.negative_ttl))
+            .collect::<Vec<_>>();
+
+        let res = match serde_json::to_string(&entries) {
+            Ok(json) => {
+                let path = self.path.clone();
+                blocking::run(move || atomic::write(&path, json)).await
+            }
+            Err(err) => Err(io::Error::other(err)),
+        };
+
+        if let Err(err) = res {
+            error!(
+                "Failed to write album art cache '{}': {err}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+fn read_entries(path: &Path) -> io::Result<Vec<CacheEntry>> {
+    let contents = fs::read_to_string(path)?;
+    serde_json::from_str(&contents).map_err(io::Error::other)
+}
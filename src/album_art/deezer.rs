@@ -0,0 +1,155 @@
+use super::http::{self, RetryPolicy};
+use super::matching;
+use super::provider::{ArtLookup, ArtProvider, BoxFuture};
+use crate::config::{DeezerConfig, DeezerCoverSize};
+use mpd_client::responses::Song;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::path::Path;
+use tracing::debug;
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    #[serde(default)]
+    data: Vec<Album>,
+    /// Deezer reports errors (such as exceeding its quota)
+    /// in the body of a successful response.
+    error: Option<ApiError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Album {
+    title: String,
+    artist: Artist,
+    cover_small: Option<String>,
+    cover_medium: Option<String>,
+    cover_big: Option<String>,
+    cover_xl: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Artist {
+    name: String,
+}
+
+impl Album {
+    fn cover(&self, size: DeezerCoverSize) -> Option<&str> {
+        match size {
+            DeezerCoverSize::Small => self.cover_small.as_deref(),
+            DeezerCoverSize::Medium => self.cover_medium.as_deref(),
+            DeezerCoverSize::Big => self.cover_big.as_deref(),
+            DeezerCoverSize::Xl => self.cover_xl.as_deref(),
+        }
+    }
+}
+
+/// Finds covers using Deezer's public search API.
+pub struct DeezerProvider {
+    client: Client,
+    retry_policy: RetryPolicy,
+    min_match_score: f64,
+    base_url: String,
+    cover_size: DeezerCoverSize,
+}
+
+impl DeezerProvider {
+    pub fn new(
+        client: Client,
+        retry_policy: RetryPolicy,
+        min_match_score: f64,
+        config: &DeezerConfig,
+    ) -> Self {
+        Self {
+            client,
+            retry_policy,
+            min_match_score,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            cover_size: config.cover_size,
+        }
+    }
+
+    async fn lookup(&self, artist: &str, album: &str) -> http::Result<ArtLookup> {
+        // Deezer's advanced search has no escaping, so quotes are dropped instead
+        let query = format!(
+            r#"artist:"{}" album:"{}""#,
+            artist.replace('"', " "),
+            album.replace('"', " ")
+        );
+        let limit = matching::SEARCH_CANDIDATES.to_string();
+
+        let request = self
+            .client
+            .get(format!("{}/search/album", self.base_url))
+            .query(&[("q", query.as_str()), ("limit", limit.as_str())]);
+
+        let response = http::send(request, None, &self.retry_policy).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(http::Error::Status(response.status()));
+        }
+
+        let response = response
+            .json::<SearchResult>()
+            .await
+            .map_err(http::Error::Decode)?;
+
+        if let Some(error) = response.error {
+            return Err(http::Error::Api(error.message));
+        }
+
+        let best = response
+            .data
+            .into_iter()
+            .filter(|result| result.cover(self.cover_size).is_some())
+            .map(|result| {
+                let score = matching::score_candidate(
+                    artist,
+                    album,
+                    &result.artist.name,
+                    &result.title,
+                    None,
+                );
+                (result, score)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match best {
+            Some((result, score)) if score >= self.min_match_score => {
+                debug!(
+                    "Matched '{artist} - {album}' to Deezer album '{} - {}' with score {score:.2}",
+                    result.artist.name, result.title
+                );
+
+                Ok(result
+                    .cover(self.cover_size)
+                    .map_or(ArtLookup::NotFound(None), |url| ArtLookup::Found {
+                        url: url.to_string(),
+                        record: None,
+                    }))
+            }
+            _ => Ok(ArtLookup::NotFound(None)),
+        }
+    }
+}
+
+impl ArtProvider for DeezerProvider {
+    fn name(&self) -> &'static str {
+        "deezer"
+    }
+
+    fn find_art<'a>(
+        &'a self,
+        _song: &'a Song,
+        _music_dir: &'a Path,
+        artist: &'a str,
+        album: &'a str,
+    ) -> BoxFuture<'a, http::Result<ArtLookup>> {
+        Box::pin(self.lookup(artist, album))
+    }
+}
@@ -0,0 +1,887 @@
+//! Reads pictures embedded in audio files, without shelling out to an external tool.
+//!
+//! Supports:
+//!
+//! - ID3v2.2 - v2.4 `PIC` / `APIC` frames (MP3, and anything else with an ID3 header)
+//! - FLAC `PICTURE` metadata blocks
+//! - MP4 `covr` atoms (M4A, ALAC)
+//! - Vorbis comment `METADATA_BLOCK_PICTURE` fields (Ogg Vorbis / Opus, and FLAC)
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Picture type for the front cover, as defined by ID3 and FLAC.
+const FRONT_COVER: u8 = 3;
+/// Picture type for an unspecified image.
+const OTHER: u8 = 0;
+
+/// Largest tag or metadata block that will be read, to guard against corrupt sizes.
+const MAX_TAG_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub struct Picture {
+    pub data: Vec<u8>,
+    /// MIME type as declared by the file, which may not match the data.
+    pub mime: Option<String>,
+    pub picture_type: u8,
+}
+
+/// Reads the front cover embedded in an audio file.
+///
+/// If there are several pictures, the one marked as the front cover is preferred,
+/// then one with no particular type, then whichever comes first.
+pub fn read_cover(path: &Path) -> io::Result<Option<Picture>> {
+    let mut pictures = read_pictures(path)?;
+
+    let index = pictures
+        .iter()
+        .position(|picture| picture.picture_type == FRONT_COVER)
+        .or_else(|| {
+            pictures
+                .iter()
+                .position(|picture| picture.picture_type == OTHER)
+        })
+        .or(if pictures.is_empty() { None } else { Some(0) });
+
+    Ok(index.map(|index| pictures.swap_remove(index)))
+}
+
+/// Reads every picture embedded in an audio file,
+/// detecting the container from its first bytes.
+fn read_pictures(path: &Path) -> io::Result<Vec<Picture>> {
+    let mut file = File::open(path)?;
+
+    let mut magic = [0; 8];
+    let read = file.read(&mut magic)?;
+    let magic = &magic[..read];
+    file.seek(SeekFrom::Start(0))?;
+
+    if magic.starts_with(b"ID3") {
+        read_id3(&mut file)
+    } else if magic.starts_with(b"fLaC") {
+        read_flac(&mut file)
+    } else if magic.starts_with(b"OggS") {
+        read_ogg(&mut file)
+    } else if magic.get(4..8) == Some(b"ftyp") {
+        read_mp4(&mut file)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Reads `len` bytes, refusing lengths which are clearly corrupt.
+fn read_exact_vec<R: Read>(reader: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_TAG_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("block of {len} bytes is too large"),
+        ));
+    }
+
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Cursor over a byte slice, returning `None` when reading past the end.
+struct Bytes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.data.len() {
+            return None;
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32_be(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_be_bytes(b.try_into().expect("4 bytes")))
+    }
+
+    fn u32_le(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().expect("4 bytes")))
+    }
+
+    /// Takes bytes up to (and consuming) a terminator,
+    /// which is a single null byte, or two aligned null bytes for UTF-16.
+    fn until_null(&mut self, wide: bool) -> Option<&'a [u8]> {
+        let end = if wide {
+            self.data
+                .chunks_exact(2)
+                .position(|c| c == [0, 0])
+                .map(|i| i * 2)?
+        } else {
+            self.data.iter().position(|&b| b == 0)?
+        };
+
+        let taken = &self.data[..end];
+        self.data = &self.data[end + if wide { 2 } else { 1 }..];
+        Some(taken)
+    }
+
+    fn rest(self) -> &'a [u8] {
+        self.data
+    }
+}
+
+// --- ID3v2 ---
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |acc, &b| (acc << 7) | u32::from(b & 0x7F))
+}
+
+/// Reverses ID3 unsynchronisation, which inserts a null byte after every `0xFF`.
+fn remove_unsync(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev_ff = false;
+
+    for &b in data {
+        if !(prev_ff && b == 0) {
+            out.push(b);
+        }
+        prev_ff = b == 0xFF;
+    }
+
+    out
+}
+
+fn read_id3<R: Read>(reader: &mut R) -> io::Result<Vec<Picture>> {
+    let mut header = [0; 10];
+    reader.read_exact(&mut header)?;
+
+    let version = header[3];
+    let flags = header[5];
+    let size = syncsafe(&header[6..10]);
+
+    let mut tag = read_exact_vec(reader, u64::from(size))?;
+
+    // v2.4 applies unsynchronisation per frame instead
+    if flags & 0x80 != 0 && version < 4 {
+        tag = remove_unsync(&tag);
+    }
+
+    let mut bytes = Bytes::new(&tag);
+
+    if flags & 0x40 != 0 && version >= 3 {
+        // extended header: v2.3 size excludes itself, v2.4 includes it
+        let skip = match version {
+            3 => bytes.u32_be().map(|size| size as usize),
+            _ => bytes
+                .take(4)
+                .map(|size| (syncsafe(size) as usize).saturating_sub(4)),
+        };
+
+        if skip.and_then(|skip| bytes.take(skip)).is_none() {
+            return Ok(Vec::new());
+        }
+    }
+
+    let mut pictures = Vec::new();
+
+    while let Some(frame) = read_id3_frame(&mut bytes, version) {
+        let Some((id, data)) = frame else {
+            continue;
+        };
+
+        let picture = match id {
+            b"PIC" => parse_id3_pic(&data),
+            b"APIC" => parse_id3_apic(&data),
+            _ => None,
+        };
+
+        pictures.extend(picture);
+    }
+
+    Ok(pictures)
+}
+
+/// Reads the next frame.
+///
+/// Returns `None` at the end of the tag,
+/// and `Some(None)` for frames which can't be read (eg compressed).
+fn read_id3_frame<'a>(bytes: &mut Bytes<'a>, version: u8) -> Option<Option<(&'a [u8], Vec<u8>)>> {
+    let (id, size, format_flags) = if version == 2 {
+        let id = bytes.take(3)?;
+        let size = bytes
+            .take(3)?
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | u32::from(b));
+        (id, size, 0)
+    } else {
+        let id = bytes.take(4)?;
+        let size = bytes.take(4)?;
+        let size = if version == 4 {
+            syncsafe(size)
+        } else {
+            u32::from_be_bytes(size.try_into().ok()?)
+        };
+        let flags = bytes.take(2)?;
+        (id, size, flags[1])
+    };
+
+    // padding
+    if id.iter().all(|&b| b == 0) {
+        return None;
+    }
+
+    let data = bytes.take(size as usize)?;
+
+    let (compressed, encrypted) = match version {
+        3 => (format_flags & 0x80 != 0, format_flags & 0x40 != 0),
+        4 => (format_flags & 0x08 != 0, format_flags & 0x04 != 0),
+        _ => (false, false),
+    };
+
+    if compressed || encrypted {
+        return Some(None);
+    }
+
+    let mut data = data;
+
+    // v2.3 grouping identity, v2.4 grouping identity / data length indicator
+    if version == 3 && format_flags & 0x20 != 0 {
+        data = data.get(1..)?;
+    }
+    if version == 4 && format_flags & 0x40 != 0 {
+        data = data.get(1..)?;
+    }
+    if version == 4 && format_flags & 0x01 != 0 {
+        data = data.get(4..)?;
+    }
+
+    let data = if version == 4 && format_flags & 0x02 != 0 {
+        remove_unsync(data)
+    } else {
+        data.to_vec()
+    };
+
+    Some(Some((id, data)))
+}
+
+/// Whether an ID3 text encoding uses two bytes per character.
+fn is_wide_encoding(encoding: u8) -> bool {
+    matches!(encoding, 1 | 2)
+}
+
+/// ID3v2.2 `PIC` frame: encoding, 3 character image format, type, description, data.
+fn parse_id3_pic(data: &[u8]) -> Option<Picture> {
+    let mut bytes = Bytes::new(data);
+
+    let encoding = bytes.u8()?;
+    let format = bytes.take(3)?;
+    let picture_type = bytes.u8()?;
+    bytes.until_null(is_wide_encoding(encoding))?;
+
+    let mime = match &format.to_ascii_uppercase()[..] {
+        b"PNG" => Some("image/png"),
+        b"JPG" => Some("image/jpeg"),
+        _ => None,
+    };
+
+    Some(Picture {
+        data: bytes.rest().to_vec(),
+        mime: mime.map(ToString::to_string),
+        picture_type,
+    })
+}
+
+/// ID3v2.3+ `APIC` frame: encoding, MIME type, type, description, data.
+fn parse_id3_apic(data: &[u8]) -> Option<Picture> {
+    let mut bytes = Bytes::new(data);
+
+    let encoding = bytes.u8()?;
+    let mime = bytes.until_null(false)?;
+    let picture_type = bytes.u8()?;
+    bytes.until_null(is_wide_encoding(encoding))?;
+
+    let mime = String::from_utf8_lossy(mime).trim().to_string();
+
+    Some(Picture {
+        data: bytes.rest().to_vec(),
+        mime: (!mime.is_empty()).then_some(mime),
+        picture_type,
+    })
+}
+
+// --- FLAC ---
+
+const FLAC_PICTURE: u8 = 6;
+const FLAC_VORBIS_COMMENT: u8 = 4;
+
+fn read_flac<R: Read>(reader: &mut R) -> io::Result<Vec<Picture>> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+
+    let mut pictures = Vec::new();
+
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+
+        let block = read_exact_vec(reader, u64::from(len))?;
+
+        match block_type {
+            FLAC_PICTURE => pictures.extend(parse_flac_picture(&block)),
+            FLAC_VORBIS_COMMENT => pictures.extend(parse_vorbis_comments(&block)),
+            _ => {}
+        }
+
+        if is_last {
+            break;
+        }
+    }
+
+    Ok(pictures)
+}
+
+/// FLAC `PICTURE` block, also used (base64 encoded) by `METADATA_BLOCK_PICTURE`.
+fn parse_flac_picture(data: &[u8]) -> Option<Picture> {
+    let mut bytes = Bytes::new(data);
+
+    let picture_type = bytes.u32_be()?;
+    let mime_len = bytes.u32_be()?;
+    let mime = bytes.take(mime_len as usize)?;
+    let desc_len = bytes.u32_be()?;
+    bytes.take(desc_len as usize)?;
+    // width, height, colour depth, indexed colour count
+    bytes.take(16)?;
+    let data_len = bytes.u32_be()?;
+    let data = bytes.take(data_len as usize)?;
+
+    let mime = String::from_utf8_lossy(mime).trim().to_string();
+
+    Some(Picture {
+        data: data.to_vec(),
+        mime: (!mime.is_empty()).then_some(mime),
+        picture_type: u8::try_from(picture_type).unwrap_or(OTHER),
+    })
+}
+
+// --- Vorbis comments ---
+
+/// Reads pictures from a Vorbis comment block
+/// (without any `\x03vorbis` / `OpusTags` packet prefix).
+fn parse_vorbis_comments(data: &[u8]) -> Vec<Picture> {
+    let mut bytes = Bytes::new(data);
+    let mut pictures = Vec::new();
+
+    let Some(vendor_len) = bytes.u32_le() else {
+        return pictures;
+    };
+    if bytes.take(vendor_len as usize).is_none() {
+        return pictures;
+    }
+
+    let Some(count) = bytes.u32_le() else {
+        return pictures;
+    };
+
+    for _ in 0..count {
+        let Some(comment) = bytes.u32_le().and_then(|len| bytes.take(len as usize)) else {
+            break;
+        };
+
+        let Some(split) = comment.iter().position(|&b| b == b'=') else {
+            continue;
+        };
+        let (key, value) = (&comment[..split], &comment[split + 1..]);
+
+        if key.eq_ignore_ascii_case(b"METADATA_BLOCK_PICTURE") {
+            pictures.extend(
+                BASE64
+                    .decode(value)
+                    .ok()
+                    .and_then(|block| parse_flac_picture(&block)),
+            );
+        } else if key.eq_ignore_ascii_case(b"COVERART") {
+            // legacy field holding just the base64 encoded image
+            pictures.extend(BASE64.decode(value).ok().map(|data| Picture {
+                data,
+                mime: None,
+                picture_type: OTHER,
+            }));
+        }
+    }
+
+    pictures
+}
+
+// --- Ogg ---
+
+fn read_ogg<R: Read>(reader: &mut R) -> io::Result<Vec<Picture>> {
+    // the comment header is the second packet of the first logical stream
+    let mut serial = None;
+    let mut packet = Vec::new();
+    let mut packet_index = 0;
+
+    loop {
+        let mut header = [0; 27];
+        reader.read_exact(&mut header)?;
+
+        if &header[0..4] != b"OggS" {
+            return Ok(Vec::new());
+        }
+
+        let page_serial = u32::from_le_bytes(header[14..18].try_into().expect("4 bytes"));
+        let segment_count = header[26];
+
+        let mut segments = vec![0; usize::from(segment_count)];
+        reader.read_exact(&mut segments)?;
+
+        let page_len = segments.iter().map(|&len| u64::from(len)).sum();
+        let page = read_exact_vec(reader, page_len)?;
+
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+
+        let mut offset = 0;
+        for len in segments {
+            let len = usize::from(len);
+            if packet_index == 1 {
+                packet.extend_from_slice(&page[offset..offset + len]);
+            }
+            offset += len;
+
+            // a segment shorter than 255 bytes ends the packet
+            if len < 255 {
+                if packet_index == 1 {
+                    return Ok(parse_ogg_comment_packet(&packet));
+                }
+                packet_index += 1;
+            }
+        }
+
+        if packet.len() as u64 > MAX_TAG_SIZE {
+            return Ok(Vec::new());
+        }
+    }
+}
+
+fn parse_ogg_comment_packet(packet: &[u8]) -> Vec<Picture> {
+    if let Some(comments) = packet.strip_prefix(b"\x03vorbis") {
+        parse_vorbis_comments(comments)
+    } else if let Some(comments) = packet.strip_prefix(b"OpusTags") {
+        parse_vorbis_comments(comments)
+    } else {
+        Vec::new()
+    }
+}
+
+// --- MP4 ---
+
+/// `data` atom type indicators for images.
+const MP4_JPEG: u32 = 13;
+const MP4_PNG: u32 = 14;
+const MP4_BMP: u32 = 27;
+
+fn read_mp4<R: Read + Seek>(reader: &mut R) -> io::Result<Vec<Picture>> {
+    // find `moov` at the top level, skipping over `mdat` and anything else
+    loop {
+        let Some((name, len)) = read_mp4_atom_header(reader)? else {
+            return Ok(Vec::new());
+        };
+
+        if &name == b"moov" {
+            let moov = read_exact_vec(reader, len)?;
+            return Ok(find_mp4_covers(&moov).unwrap_or_default());
+        }
+
+        reader.seek(SeekFrom::Current(i64::try_from(len).unwrap_or(i64::MAX)))?;
+    }
+}
+
+/// Reads an atom's name and the length of its body.
+fn read_mp4_atom_header<R: Read>(reader: &mut R) -> io::Result<Option<([u8; 4], u64)>> {
+    let mut header = [0; 8];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+
+    let size = u32::from_be_bytes(header[0..4].try_into().expect("4 bytes"));
+    let name = header[4..8].try_into().expect("4 bytes");
+
+    let len = match size {
+        // extends to the end of the file
+        0 => return Ok(None),
+        1 => {
+            let mut size = [0; 8];
+            reader.read_exact(&mut size)?;
+            u64::from_be_bytes(size).saturating_sub(16)
+        }
+        size => u64::from(size).saturating_sub(8),
+    };
+
+    Ok(Some((name, len)))
+}
+
+/// Iterates over the child atoms in an atom's body.
+fn mp4_children(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut bytes = Bytes::new(data);
+
+    std::iter::from_fn(move || {
+        let size = bytes.u32_be()? as usize;
+        let name = bytes.take(4)?;
+        let body = bytes.take(size.checked_sub(8)?)?;
+        Some((name, body))
+    })
+}
+
+fn find_mp4_child<'a>(data: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    mp4_children(data)
+        .find(|(child, _)| *child == name)
+        .map(|(_, body)| body)
+}
+
+/// Finds `moov.udta.meta.ilst.covr` and reads each `data` atom in it.
+fn find_mp4_covers(moov: &[u8]) -> Option<Vec<Picture>> {
+    let udta = find_mp4_child(moov, b"udta")?;
+    // `meta` is a full atom, with 4 bytes of version and flags before its children
+    let meta = find_mp4_child(udta, b"meta")?.get(4..)?;
+    let ilst = find_mp4_child(meta, b"ilst")?;
+    let covr = find_mp4_child(ilst, b"covr")?;
+
+    let pictures = mp4_children(covr)
+        .filter(|(name, _)| *name == b"data")
+        .filter_map(|(_, body)| {
+            let mut bytes = Bytes::new(body);
+            let data_type = bytes.u32_be()? & 0x00FF_FFFF;
+            // locale
+            bytes.take(4)?;
+
+            let mime = match data_type {
+                MP4_JPEG => Some("image/jpeg"),
+                MP4_PNG => Some("image/png"),
+                MP4_BMP => Some("image/bmp"),
+                _ => None,
+            };
+
+            Some(Picture {
+                data: bytes.rest().to_vec(),
+                mime: mime.map(ToString::to_string),
+                // MP4 doesn't record picture types, but the first is the cover by convention
+                picture_type: OTHER,
+            })
+        })
+        .collect();
+
+    Some(pictures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0\x00\x10JFIF\x00\xFF\x00\xFF\xFF\xD9";
+    const PNG: &[u8] = b"\x89PNG\r\n\x1A\n\x00\x00\x00\x0DIHDR";
+
+    fn syncsafe_bytes(value: u32) -> [u8; 4] {
+        [
+            (value >> 21) as u8 & 0x7F,
+            (value >> 14) as u8 & 0x7F,
+            (value >> 7) as u8 & 0x7F,
+            value as u8 & 0x7F,
+        ]
+    }
+
+    /// Applies unsynchronisation by inserting a null byte after every `0xFF`.
+    fn unsync(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &b in data {
+            out.push(b);
+            if b == 0xFF {
+                out.push(0);
+            }
+        }
+        out
+    }
+
+    fn id3_tag(version: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut tag = b"ID3".to_vec();
+        tag.extend([version, 0, flags]);
+        tag.extend(syncsafe_bytes(body.len() as u32));
+        tag.extend(body);
+        tag
+    }
+
+    fn id3_frame(version: u8, id: &[u8], flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        if version == 4 {
+            frame.extend(syncsafe_bytes(body.len() as u32));
+        } else {
+            frame.extend((body.len() as u32).to_be_bytes());
+        }
+        frame.extend([0, flags]);
+        frame.extend(body);
+        frame
+    }
+
+    fn apic(
+        encoding: u8,
+        mime: &str,
+        picture_type: u8,
+        description: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut body = vec![encoding];
+        body.extend(mime.as_bytes());
+        body.push(0);
+        body.push(picture_type);
+        body.extend(description);
+        body.extend(data);
+        body
+    }
+
+    fn flac_picture(picture_type: u32, mime: &str, data: &[u8]) -> Vec<u8> {
+        let mut block = picture_type.to_be_bytes().to_vec();
+        block.extend((mime.len() as u32).to_be_bytes());
+        block.extend(mime.as_bytes());
+        block.extend(5u32.to_be_bytes());
+        block.extend(b"Cover");
+        block.extend([0; 16]);
+        block.extend((data.len() as u32).to_be_bytes());
+        block.extend(data);
+        block
+    }
+
+    fn vorbis_comments(comments: &[String]) -> Vec<u8> {
+        let vendor = b"test";
+        let mut block = (vendor.len() as u32).to_le_bytes().to_vec();
+        block.extend(vendor);
+        block.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend((comment.len() as u32).to_le_bytes());
+            block.extend(comment.as_bytes());
+        }
+        block
+    }
+
+    fn ogg_page(serial: u32, segments: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        // version, header type, granule position
+        page.extend([0; 10]);
+        page.extend(serial.to_le_bytes());
+        // sequence number, checksum
+        page.extend([0; 8]);
+        page.push(segments.len() as u8);
+        page.extend(segments);
+        page.extend(payload);
+        page
+    }
+
+    fn mp4_atom(name: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend(name);
+        atom.extend(body);
+        atom
+    }
+
+    fn mp4_data(data_type: u32, data: &[u8]) -> Vec<u8> {
+        let mut body = data_type.to_be_bytes().to_vec();
+        // locale
+        body.extend([0; 4]);
+        body.extend(data);
+        mp4_atom(b"data", &body)
+    }
+
+    #[test]
+    fn reads_id3v23_with_tag_unsync() {
+        let frame = id3_frame(3, b"APIC", 0, &apic(0, "image/jpeg", 3, b"\0", JPEG));
+        let tag = id3_tag(3, 0x80, &unsync(&frame));
+
+        let pictures = read_id3(&mut Cursor::new(tag)).expect("tag to be read");
+
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data, JPEG);
+        assert_eq!(pictures[0].mime.as_deref(), Some("image/jpeg"));
+        assert_eq!(pictures[0].picture_type, FRONT_COVER);
+    }
+
+    #[test]
+    fn reads_id3v24_with_frame_unsync_and_data_length() {
+        let body = apic(3, "image/jpeg", 3, b"\0", JPEG);
+
+        let mut frame_body = syncsafe_bytes(body.len() as u32).to_vec();
+        frame_body.extend(unsync(&body));
+
+        // the tag's unsynchronisation flag only means frames may be unsynchronised in v2.4,
+        // so must not be reversed a second time
+        let tag = id3_tag(4, 0x80, &id3_frame(4, b"APIC", 0x03, &frame_body));
+
+        let pictures = read_id3(&mut Cursor::new(tag)).expect("tag to be read");
+
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data, JPEG);
+        assert_eq!(pictures[0].picture_type, FRONT_COVER);
+    }
+
+    #[test]
+    fn reads_utf16_apic_description() {
+        // "CĀ" with a byte order mark, where the unaligned bytes `00 00`
+        // must not be taken as the terminator
+        let description = b"\xFF\xFEC\x00\x00\x01\x00\x00";
+        let frame = id3_frame(3, b"APIC", 0, &apic(1, "image/png", 3, description, PNG));
+        let tag = id3_tag(3, 0, &frame);
+
+        let pictures = read_id3(&mut Cursor::new(tag)).expect("tag to be read");
+
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data, PNG);
+        assert_eq!(pictures[0].mime.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn reads_flac_picture_and_vorbis_picture() {
+        let comment = format!(
+            "METADATA_BLOCK_PICTURE={}",
+            BASE64.encode(flac_picture(4, "image/png", PNG))
+        );
+
+        let mut file = b"fLaC".to_vec();
+        for (block_type, block) in [
+            (0, vec![0; 34]),
+            (FLAC_PICTURE, flac_picture(3, "image/jpeg", JPEG)),
+            (FLAC_VORBIS_COMMENT | 0x80, vorbis_comments(&[comment])),
+        ] {
+            file.push(block_type);
+            file.extend(&(block.len() as u32).to_be_bytes()[1..]);
+            file.extend(block);
+        }
+
+        let pictures = read_flac(&mut Cursor::new(file)).expect("metadata to be read");
+
+        assert_eq!(pictures.len(), 2);
+        assert_eq!(pictures[0].data, JPEG);
+        assert_eq!(pictures[0].mime.as_deref(), Some("image/jpeg"));
+        assert_eq!(pictures[0].picture_type, FRONT_COVER);
+        assert_eq!(pictures[1].data, PNG);
+        assert_eq!(pictures[1].mime.as_deref(), Some("image/png"));
+        assert_eq!(pictures[1].picture_type, 4);
+    }
+
+    #[test]
+    fn reads_ogg_comment_packet_split_across_pages() {
+        let image = JPEG.repeat(40);
+        let comment = format!(
+            "METADATA_BLOCK_PICTURE={}",
+            BASE64.encode(flac_picture(3, "image/jpeg", &image))
+        );
+
+        let mut packet = b"\x03vorbis".to_vec();
+        packet.extend(vorbis_comments(&["ARTIST=Someone".to_string(), comment]));
+        assert!(packet.len() > 255 * 2);
+
+        let identification = b"\x01vorbis identification";
+
+        // the first two segments on one page, the rest on the next
+        let (first, rest) = packet.split_at(255 * 2);
+        let mut rest_segments = vec![255; rest.len() / 255];
+        rest_segments.push((rest.len() % 255) as u8);
+
+        let mut file = ogg_page(1, &[identification.len() as u8], identification);
+        file.extend(ogg_page(1, &[255, 255], first));
+        // a page from another logical stream in between
+        file.extend(ogg_page(2, &[4], b"skip"));
+        file.extend(ogg_page(1, &rest_segments, rest));
+
+        let pictures = read_ogg(&mut Cursor::new(file)).expect("pages to be read");
+
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures[0].data, image);
+        assert_eq!(pictures[0].picture_type, FRONT_COVER);
+    }
+
+    #[test]
+    fn reads_mp4_covr() {
+        let covr = mp4_atom(
+            b"covr",
+            &[mp4_data(MP4_JPEG, JPEG), mp4_data(MP4_PNG, PNG)].concat(),
+        );
+        let ilst = mp4_atom(b"ilst", &covr);
+        let meta = mp4_atom(b"meta", &[&[0; 4][..], &ilst].concat());
+        let moov = mp4_atom(b"moov", &mp4_atom(b"udta", &meta));
+
+        let mut file = mp4_atom(b"ftyp", b"M4A \0\0\0\0");
+        file.extend(mp4_atom(b"mdat", &[0; 64]));
+        file.extend(moov);
+
+        let pictures = read_mp4(&mut Cursor::new(file)).expect("atoms to be read");
+
+        assert_eq!(pictures.len(), 2);
+        assert_eq!(pictures[0].data, JPEG);
+        assert_eq!(pictures[0].mime.as_deref(), Some("image/jpeg"));
+        assert_eq!(pictures[1].data, PNG);
+        assert_eq!(pictures[1].mime.as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn prefers_front_cover() {
+        let dir = tempfile::tempdir().expect("temp dir to be created");
+
+        let write_mp3 = |name: &str, types: &[u8]| {
+            let frames = types
+                .iter()
+                .map(|&picture_type| {
+                    let data = [JPEG, &[picture_type]].concat();
+                    id3_frame(
+                        3,
+                        b"APIC",
+                        0,
+                        &apic(0, "image/jpeg", picture_type, b"\0", &data),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .concat();
+
+            let path = dir.path().join(name);
+            fs::write(&path, id3_tag(3, 0, &frames)).expect("file to be written");
+            path
+        };
+
+        let cover = |path: PathBuf| {
+            read_cover(&path)
+                .expect("file to be read")
+                .expect("a picture")
+                .picture_type
+        };
+
+        assert_eq!(
+            cover(write_mp3("front.mp3", &[4, OTHER, FRONT_COVER])),
+            FRONT_COVER
+        );
+        assert_eq!(cover(write_mp3("other.mp3", &[4, OTHER, 5])), OTHER);
+        assert_eq!(cover(write_mp3("first.mp3", &[4, 5])), 4);
+
+        let none = write_mp3("none.mp3", &[]);
+        assert!(read_cover(&none).expect("file to be read").is_none());
+    }
+}
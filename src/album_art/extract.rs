@@ -0,0 +1,163 @@
+use super::{atomic, blocking, embedded};
+use mpd_client::Client;
+use mpd_client::responses::Song;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// File extensions covers may be saved with.
+pub const IMAGE_EXTENSIONS: &[&str] = &["jpg", "png", "gif", "webp", "bmp"];
+
+/// Extracts the cover for `song`, saving it to `<dir>/<name>.<ext>`.
+///
+/// The cover is read through MPD first, using `readpicture` for embedded art
+/// and then `albumart` for a cover file in the song's directory,
+/// so this works when MPD runs on another machine.
+/// If MPD has nothing and `embedded_fallback` is set,
+/// the cover embedded in `audio_path` is read instead, which must be on this machine.
+///
+/// Returns the path the cover was saved to.
+pub async fn extract_cover(
+    mpd: &Client,
+    song: &Song,
+    audio_path: PathBuf,
+    dir: PathBuf,
+    name: String,
+    embedded_fallback: bool,
+) -> Option<PathBuf> {
+    let cover = read_from_mpd(mpd, &song.url).await;
+
+    if cover.is_none() && !embedded_fallback {
+        return None;
+    }
+
+    blocking::run(move || {
+        let (data, mime) = match cover {
+            Some(cover) => cover,
+            None => read_embedded(&audio_path)?,
+        };
+
+        save_cover(&dir, &name, &data, mime.as_deref())
+    })
+    .await
+}
+
+/// Copies a cover image file to `<dir>/<name>.<ext>`.
+///
+/// Returns the path the cover was saved to.
+pub async fn copy_cover(src: PathBuf, dir: PathBuf, name: String) -> Option<PathBuf> {
+    blocking::run(move || match fs::read(&src) {
+        Ok(data) => save_cover(&dir, &name, &data, None),
+        Err(err) => {
+            warn!("Failed to read cover '{}': {err}", src.display());
+            None
+        }
+    })
+    .await
+}
+
+fn save_cover(dir: &Path, name: &str, data: &[u8], mime: Option<&str>) -> Option<PathBuf> {
+    let path = dir.join(format!("{name}.{}", image_extension(mime, data)));
+
+    match atomic::write(&path, data) {
+        Ok(()) => Some(path),
+        Err(err) => {
+            warn!("Failed to write cover '{}': {err}", path.display());
+            None
+        }
+    }
+}
+
+/// Finds a cover previously saved as `<dir>/<name>.<ext>`.
+pub fn find_extracted_cover(dir: &Path, name: &str) -> Option<PathBuf> {
+    IMAGE_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|path| path.exists())
+}
+
+async fn read_from_mpd(mpd: &Client, uri: &str) -> Option<(Vec<u8>, Option<String>)> {
+    match mpd.album_art(uri).await {
+        Ok(Some((data, mime))) => {
+            debug!("Read {} byte cover from MPD", data.len());
+            Some((data.to_vec(), mime))
+        }
+        Ok(None) => {
+            debug!("MPD has no cover for song");
+            None
+        }
+        Err(err) => {
+            warn!("Failed to read cover from MPD: {err}");
+            None
+        }
+    }
+}
+
+fn read_embedded(audio_path: &Path) -> Option<(Vec<u8>, Option<String>)> {
+    match embedded::read_cover(audio_path) {
+        Ok(Some(picture)) => {
+            debug!(
+                "Read {} byte embedded cover from '{}'",
+                picture.data.len(),
+                audio_path.display()
+            );
+            Some((picture.data, picture.mime))
+        }
+        Ok(None) => {
+            debug!("No embedded cover in '{}'", audio_path.display());
+            None
+        }
+        Err(err) => {
+            warn!(
+                "Failed to read embedded cover from '{}': {err}",
+                audio_path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Gets the file extension for an image,
+/// from its magic bytes if recognised, otherwise from its MIME type.
+///
+/// The magic bytes take precedence, as tags often declare the wrong type.
+pub fn image_extension(mime: Option<&str>, data: &[u8]) -> &'static str {
+    match mime.map(str::to_ascii_lowercase).as_deref() {
+        _ if data.starts_with(b"\xFF\xD8\xFF") => "jpg",
+        _ if data.starts_with(b"\x89PNG") => "png",
+        _ if data.starts_with(b"GIF8") => "gif",
+        _ if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") => "webp",
+        _ if data.starts_with(b"BM") => "bmp",
+        Some("image/png") => "png",
+        Some("image/gif") => "gif",
+        Some("image/webp") => "webp",
+        Some("image/bmp") => "bmp",
+        _ => "jpg",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_image_extension_from_magic_bytes() {
+        // the data takes precedence over the declared type
+        assert_eq!(
+            image_extension(Some("image/png"), b"\xFF\xD8\xFF\xE0"),
+            "jpg"
+        );
+        assert_eq!(image_extension(None, b"\x89PNG\r\n\x1A\n"), "png");
+        assert_eq!(image_extension(None, b"GIF89a"), "gif");
+        assert_eq!(image_extension(None, b"RIFF\0\0\0\0WEBPVP8 "), "webp");
+        assert_eq!(image_extension(None, b"BM\0\0"), "bmp");
+
+        // falls back to the declared type, then jpg
+        assert_eq!(image_extension(Some("IMAGE/PNG"), b"????"), "png");
+        assert_eq!(
+            image_extension(Some("image/webp"), b"RIFF\0\0\0\0WAVE"),
+            "webp"
+        );
+        assert_eq!(image_extension(None, b"????"), "jpg");
+    }
+}
@@ -0,0 +1,368 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{Instant, sleep, sleep_until};
+use tracing::{debug, warn};
+
+/// Longest delay between two attempts at the same request,
+/// regardless of what the server asks for.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum Error {
+    /// The request could not be sent or timed out.
+    Request(reqwest::Error),
+    /// The server responded with an unexpected status code.
+    Status(StatusCode),
+    /// The response body was not in the expected format.
+    Decode(reqwest::Error),
+    /// The service reported an error in an otherwise successful response.
+    Api(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(err) => write!(f, "request failed: {err}"),
+            Self::Status(status) => write!(f, "server responded with {status}"),
+            Self::Decode(err) => write!(f, "unexpected response body: {err}"),
+            Self::Api(message) => write!(f, "service reported an error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Token bucket rate limiter.
+///
+/// Holds up to `capacity` tokens, refilled at `per_second` tokens a second.
+/// Each request consumes a single token, waiting for one to become available.
+pub struct RateLimiter {
+    capacity: f64,
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    blocked_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, per_second: f64) -> Self {
+        let capacity = f64::from(capacity.max(1));
+
+        Self {
+            capacity,
+            per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: Instant::now(),
+                blocked_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a token is available, then consumes it.
+    ///
+    /// The lock is held while waiting, so callers are served in order.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+
+        loop {
+            if let Some(blocked_until) = bucket.blocked_until.take() {
+                sleep_until(blocked_until).await;
+            }
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.capacity);
+            bucket.last_refill = now;
+
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                return;
+            }
+
+            let wait = (1.0 - bucket.tokens) / self.per_second;
+            sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+
+    /// Stops any further requests from going out for `delay`,
+    /// for when the server reports it is being sent too many.
+    pub async fn block_for(&self, delay: Duration) {
+        let mut bucket = self.bucket.lock().await;
+        let until = Instant::now() + delay;
+
+        bucket.blocked_until = Some(bucket.blocked_until.map_or(until, |prev| prev.max(until)));
+        bucket.tokens = 0.0;
+    }
+}
+
+/// How failed requests are retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each subsequent one.
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_BACKOFF)
+    }
+}
+
+/// Sends a request, retrying on timeouts, connection errors,
+/// rate limiting and server errors.
+///
+/// If `limiter` is set, a token is taken before each attempt.
+/// A `Retry-After` header on the response takes precedence over the policy's backoff,
+/// and is also applied to the limiter so other requests wait too.
+///
+/// Returns the response for any status not worth retrying,
+/// leaving it to the caller to decide what counts as a success.
+pub async fn send(
+    request: RequestBuilder,
+    limiter: Option<&RateLimiter>,
+    policy: &RetryPolicy,
+) -> Result<Response> {
+    let mut attempt = 0;
+
+    loop {
+        let Some(this_request) = request.try_clone() else {
+            // streaming bodies cannot be cloned, so can only be tried once
+            return request.send().await.map_err(Error::Request);
+        };
+
+        if let Some(limiter) = limiter {
+            limiter.acquire().await;
+        }
+
+        let result = this_request.send().await;
+
+        let (err, retry_after) = match result {
+            Ok(response) if is_retryable_status(response.status()) => {
+                let retry_after = get_retry_after(&response);
+                (Error::Status(response.status()), retry_after)
+            }
+            Ok(response) => return Ok(response),
+            Err(err) if err.is_timeout() || err.is_connect() || err.is_request() => {
+                (Error::Request(err), None)
+            }
+            Err(err) => return Err(Error::Request(err)),
+        };
+
+        if attempt >= policy.max_retries {
+            warn!("Giving up after {} attempts: {err}", attempt + 1);
+            return Err(err);
+        }
+
+        let delay = retry_after.unwrap_or_else(|| policy.backoff(attempt));
+        debug!("Request failed ({err}), retrying in {delay:?}");
+
+        match (limiter, retry_after) {
+            (Some(limiter), Some(delay)) => limiter.block_for(delay).await,
+            _ => sleep(delay).await,
+        }
+
+        attempt += 1;
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Reads the `Retry-After` header,
+/// which is either a number of seconds or an HTTP date.
+fn get_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    let delay = if let Ok(secs) = value.parse::<u64>() {
+        Duration::from_secs(secs)
+    } else {
+        let date = DateTime::parse_from_rfc2822(value).ok()?;
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default()
+    };
+
+    Some(delay.min(MAX_BACKOFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album_art::test_server::{Response as TestResponse, TestServer};
+    use reqwest::Client;
+
+    /// Starts a server giving each response in turn, then `200 OK`.
+    fn server(responses: Vec<TestResponse>) -> TestServer {
+        let responses = std::sync::Mutex::new(responses.into_iter());
+
+        TestServer::start(move |_| {
+            responses
+                .lock()
+                .expect("lock not poisoned")
+                .next()
+                .unwrap_or_else(|| (200, "ok".to_string()).into())
+        })
+    }
+
+    fn retry_after(status: u16, value: String) -> TestResponse {
+        TestResponse {
+            status,
+            headers: vec![("Retry-After".to_string(), value)],
+            body: String::new(),
+        }
+    }
+
+    fn policy(max_retries: u32, base_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_server_errors_and_rate_limiting() {
+        let server = server(vec![
+            (503, String::new()).into(),
+            (429, String::new()).into(),
+        ]);
+
+        let start = Instant::now();
+        let response = send(
+            Client::new().get(&server.url),
+            None,
+            &policy(3, Duration::from_secs(1)),
+        )
+        .await
+        .expect("request to succeed");
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 3);
+        // backs off 1s, then 2s
+        assert!(start.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_retries() {
+        let server = TestServer::start(|_| (503, String::new()));
+
+        let result = send(
+            Client::new().get(&server.url),
+            None,
+            &policy(2, Duration::from_millis(10)),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(Error::Status(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_client_errors() {
+        let server = TestServer::start(|_| (404, String::new()));
+
+        let response = send(
+            Client::new().get(&server.url),
+            None,
+            &policy(2, Duration::from_millis(10)),
+        )
+        .await
+        .expect("request to be sent");
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn honours_retry_after_seconds() {
+        let server = server(vec![retry_after(429, "5".to_string())]);
+
+        let start = Instant::now();
+        send(
+            Client::new().get(&server.url),
+            None,
+            &policy(1, Duration::from_millis(10)),
+        )
+        .await
+        .expect("request to succeed");
+
+        assert_eq!(server.requests().len(), 2);
+        assert!(start.elapsed() >= Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn honours_retry_after_date() {
+        let date = (Utc::now() + chrono::TimeDelta::seconds(5))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        let server = server(vec![retry_after(503, date)]);
+
+        let start = Instant::now();
+        send(
+            Client::new().get(&server.url),
+            None,
+            &policy(1, Duration::from_millis(10)),
+        )
+        .await
+        .expect("request to succeed");
+
+        assert_eq!(server.requests().len(), 2);
+        // the date is only to the second, and the clock keeps running during the request
+        assert!(start.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_blocks_the_limiter() {
+        let server = server(vec![retry_after(429, "5".to_string())]);
+        let limiter = RateLimiter::new(10, 10.0);
+
+        send(
+            Client::new().get(&server.url),
+            Some(&limiter),
+            &policy(1, Duration::from_millis(10)),
+        )
+        .await
+        .expect("request to succeed");
+
+        // other requests through the limiter waited too
+        let start = Instant::now();
+        limiter.block_for(Duration::from_secs(2)).await;
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limiter_refills_at_rate() {
+        let limiter = RateLimiter::new(2, 1.0);
+        let start = Instant::now();
+
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert!(start.elapsed() < Duration::from_millis(10));
+
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        limiter.block_for(Duration::from_secs(5)).await;
+        limiter.acquire().await;
+        assert!(start.elapsed() >= Duration::from_secs(6));
+    }
+}
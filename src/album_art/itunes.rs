@@ -0,0 +1,242 @@
+use super::http::{self, RetryPolicy};
+use super::matching;
+use super::provider::{ArtLookup, ArtProvider, BoxFuture};
+use crate::config::ItunesConfig;
+use mpd_client::responses::Song;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::path::Path;
+use tracing::debug;
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    results: Vec<Album>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Album {
+    collection_name: String,
+    artist_name: String,
+    artwork_url100: Option<String>,
+}
+
+/// Finds covers using the iTunes Search API.
+pub struct ItunesProvider {
+    client: Client,
+    retry_policy: RetryPolicy,
+    min_match_score: f64,
+    base_url: String,
+    artwork_size: u32,
+    country: Option<String>,
+}
+
+impl ItunesProvider {
+    pub fn new(
+        client: Client,
+        retry_policy: RetryPolicy,
+        min_match_score: f64,
+        config: &ItunesConfig,
+    ) -> Self {
+        Self {
+            client,
+            retry_policy,
+            min_match_score,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            artwork_size: config.artwork_size,
+            country: config.country.clone(),
+        }
+    }
+
+    async fn lookup(&self, artist: &str, album: &str) -> http::Result<ArtLookup> {
+        let term = format!("{artist} {album}");
+        let limit = matching::SEARCH_CANDIDATES.to_string();
+
+        let mut query = vec![
+            ("term", term.as_str()),
+            ("media", "music"),
+            ("entity", "album"),
+            ("limit", limit.as_str()),
+        ];
+
+        if let Some(country) = &self.country {
+            query.push(("country", country));
+        }
+
+        let request = self
+            .client
+            .get(format!("{}/search", self.base_url))
+            .query(&query);
+
+        let response = http::send(request, None, &self.retry_policy).await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(http::Error::Status(response.status()));
+        }
+
+        let response = response
+            .json::<SearchResult>()
+            .await
+            .map_err(http::Error::Decode)?;
+
+        let best = response
+            .results
+            .into_iter()
+            .filter(|result| result.artwork_url100.is_some())
+            .map(|result| {
+                let score = matching::score_candidate(
+                    artist,
+                    album,
+                    &result.artist_name,
+                    &result.collection_name,
+                    None,
+                );
+                (result, score)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match best {
+            Some((result, score)) if score >= self.min_match_score => {
+                debug!(
+                    "Matched '{artist} - {album}' to iTunes album '{} - {}' with score {score:.2}",
+                    result.artist_name, result.collection_name
+                );
+
+                let url = result.artwork_url100.unwrap_or_default();
+                Ok(ArtLookup::Found {
+                    url: resize_artwork_url(&url, self.artwork_size),
+                    record: None,
+                })
+            }
+            _ => Ok(ArtLookup::NotFound(None)),
+        }
+    }
+}
+
+impl ArtProvider for ItunesProvider {
+    fn name(&self) -> &'static str {
+        "itunes"
+    }
+
+    fn find_art<'a>(
+        &'a self,
+        _song: &'a Song,
+        _music_dir: &'a Path,
+        artist: &'a str,
+        album: &'a str,
+    ) -> BoxFuture<'a, http::Result<ArtLookup>> {
+        Box::pin(self.lookup(artist, album))
+    }
+}
+
+/// Rewrites an artwork URL to request a different size.
+///
+/// iTunes returns URLs ending in eg `100x100bb.jpg`,
+/// where the dimensions can be replaced to get any size up to the original.
+fn resize_artwork_url(url: &str, size: u32) -> String {
+    match url.rsplit_once('/') {
+        Some((base, file_name)) if file_name.starts_with("100x100") => {
+            format!("{base}/{size}x{size}{}", &file_name["100x100".len()..])
+        }
+        _ => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album_art::test_server::TestServer;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn provider(server: &TestServer) -> ItunesProvider {
+        ItunesProvider::new(
+            Client::new(),
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+            0.75,
+            &ItunesConfig {
+                base_url: server.url.clone(),
+                artwork_size: 600,
+                country: None,
+            },
+        )
+    }
+
+    fn album(artist: &str, album: &str, artwork: &str) -> serde_json::Value {
+        json!({
+            "collectionName": album,
+            "artistName": artist,
+            "artworkUrl100": format!("https://is1-ssl.mzstatic.com/image/thumb/{artwork}/100x100bb.jpg"),
+        })
+    }
+
+    #[tokio::test]
+    async fn picks_best_matching_album() {
+        let server = TestServer::start(|_| {
+            let results = json!({
+                "results": [
+                    album("Radiohead", "OK Computer OKNOTOK 1997 2017", "oknotok"),
+                    album("Radiohead", "OK Computer", "ok-computer"),
+                    album("Radiohead Tribute Band", "OK Computer (Tribute)", "tribute"),
+                ]
+            });
+            (200, results.to_string())
+        });
+
+        let lookup = provider(&server)
+            .lookup("Radiohead", "OK Computer")
+            .await
+            .expect("lookup to succeed");
+
+        match lookup {
+            ArtLookup::Found { url, record } => {
+                assert_eq!(
+                    url,
+                    "https://is1-ssl.mzstatic.com/image/thumb/ok-computer/600x600bb.jpg"
+                );
+                assert!(record.is_none());
+            }
+            lookup => panic!("expected a match, got {lookup:?}"),
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        assert!(requests[0].path.starts_with("/search?"));
+        assert!(requests[0].path.contains("entity=album"));
+    }
+
+    #[tokio::test]
+    async fn rejects_match_below_min_score() {
+        let server = TestServer::start(|_| {
+            let results = json!({
+                "results": [album("Some Other Artist", "Greatest Hits", "hits")]
+            });
+            (200, results.to_string())
+        });
+
+        let lookup = provider(&server)
+            .lookup("Radiohead", "OK Computer")
+            .await
+            .expect("lookup to succeed");
+
+        assert!(matches!(lookup, ArtLookup::NotFound(None)), "{lookup:?}");
+    }
+
+    #[test]
+    fn resizes_artwork_url() {
+        assert_eq!(
+            resize_artwork_url("https://example.com/a/b/100x100bb.jpg", 1200),
+            "https://example.com/a/b/1200x1200bb.jpg"
+        );
+
+        // left alone if not in the expected format
+        assert_eq!(
+            resize_artwork_url("https://example.com/a/b/cover.jpg", 1200),
+            "https://example.com/a/b/cover.jpg"
+        );
+    }
+}
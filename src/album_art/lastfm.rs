@@ -0,0 +1,174 @@
+use super::http::{self, RetryPolicy};
+use super::matching;
+use super::provider::{ArtLookup, ArtProvider, BoxFuture};
+use crate::config::LastfmConfig;
+use crate::mpd_conn::try_get_first_tag;
+use mpd_client::responses::Song;
+use mpd_client::tag::Tag;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::env;
+use std::path::Path;
+use tracing::debug;
+
+/// Environment variable the API key is read from if not set in the config.
+const API_KEY_ENV: &str = "LASTFM_API_KEY";
+
+/// Last.fm error code for a missing album.
+const ERROR_NOT_FOUND: u32 = 6;
+
+/// Last.fm returns this image, rather than none at all,
+/// for albums without a cover.
+const PLACEHOLDER_IMAGE: &str = "2a96cbd8b46e442fc41c2b86b821562f";
+
+/// Image sizes in the order Last.fm uses them, smallest first.
+const IMAGE_SIZES: &[&str] = &["small", "medium", "large", "extralarge", "mega"];
+
+#[derive(Deserialize, Debug)]
+struct InfoResult {
+    album: Option<Album>,
+    error: Option<u32>,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Album {
+    name: String,
+    artist: String,
+    #[serde(default)]
+    image: Vec<Image>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Image {
+    #[serde(rename = "#text")]
+    url: String,
+    size: String,
+}
+
+impl Album {
+    /// Gets the URL of the largest image.
+    fn largest_image(&self) -> Option<&str> {
+        self.image
+            .iter()
+            .filter(|image| !image.url.is_empty() && !image.url.contains(PLACEHOLDER_IMAGE))
+            .max_by_key(|image| IMAGE_SIZES.iter().position(|size| *size == image.size))
+            .map(|image| image.url.as_str())
+    }
+}
+
+/// Finds covers using Last.fm's `album.getInfo` method.
+pub struct LastfmProvider {
+    client: Client,
+    retry_policy: RetryPolicy,
+    min_match_score: f64,
+    base_url: String,
+    api_key: String,
+}
+
+impl LastfmProvider {
+    /// Creates the provider, or returns `None`
+    /// if no API key is set in the config or environment.
+    pub fn new(
+        client: Client,
+        retry_policy: RetryPolicy,
+        min_match_score: f64,
+        config: &LastfmConfig,
+    ) -> Option<Self> {
+        let api_key = config
+            .api_key
+            .clone()
+            .or_else(|| env::var(API_KEY_ENV).ok())
+            .filter(|key| !key.is_empty())?;
+
+        Some(Self {
+            client,
+            retry_policy,
+            min_match_score,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            api_key,
+        })
+    }
+
+    /// Calls `album.getInfo`, returning `None` if Last.fm doesn't know the album.
+    async fn get_info(&self, params: &[(&str, &str)]) -> http::Result<Option<Album>> {
+        let request = self
+            .client
+            .get(format!("{}/", self.base_url))
+            .query(&[
+                ("method", "album.getinfo"),
+                ("api_key", &self.api_key),
+                ("format", "json"),
+            ])
+            .query(params);
+
+        let response = http::send(request, None, &self.retry_policy).await?;
+
+        // errors are reported in the body, sometimes alongside a 4xx status
+        if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+            return Err(http::Error::Status(response.status()));
+        }
+
+        let response = response
+            .json::<InfoResult>()
+            .await
+            .map_err(http::Error::Decode)?;
+
+        match response.error {
+            Some(ERROR_NOT_FOUND) => Ok(None),
+            Some(code) => Err(http::Error::Api(format!("{} ({code})", response.message))),
+            None => Ok(response.album),
+        }
+    }
+
+    /// Looks the album up by its MusicBrainz release ID if tagged,
+    /// otherwise (or if Last.fm doesn't know the ID) by artist and album.
+    async fn lookup(&self, song: &Song, artist: &str, album: &str) -> http::Result<ArtLookup> {
+        let mbid = try_get_first_tag(song.tags.get(&Tag::MusicBrainzReleaseId));
+
+        let mut info = match mbid {
+            Some(mbid) => self.get_info(&[("mbid", mbid)]).await?,
+            None => None,
+        };
+
+        if info.is_none() {
+            info = self
+                .get_info(&[("artist", artist), ("album", album), ("autocorrect", "1")])
+                .await?
+                .filter(|info| {
+                    // autocorrect may return a different album entirely
+                    let score =
+                        matching::score_candidate(artist, album, &info.artist, &info.name, None);
+                    score >= self.min_match_score
+                });
+        }
+
+        match info.as_ref().and_then(Album::largest_image) {
+            Some(url) => {
+                debug!("Found Last.fm cover for '{artist} - {album}'");
+                Ok(ArtLookup::Found {
+                    url: url.to_string(),
+                    record: None,
+                })
+            }
+            None => Ok(ArtLookup::NotFound(None)),
+        }
+    }
+}
+
+impl ArtProvider for LastfmProvider {
+    fn name(&self) -> &'static str {
+        "lastfm"
+    }
+
+    fn find_art<'a>(
+        &'a self,
+        song: &'a Song,
+        _music_dir: &'a Path,
+        artist: &'a str,
+        album: &'a str,
+    ) -> BoxFuture<'a, http::Result<ArtLookup>> {
+        Box::pin(self.lookup(song, artist, album))
+    }
+}
@@ -0,0 +1,139 @@
+use super::blocking;
+use super::extract::IMAGE_EXTENSIONS;
+use super::http;
+use super::provider::{ArtLookup, ArtProvider, BoxFuture};
+use crate::config::LocalConfig;
+use glob::{MatchOptions, Pattern};
+use mpd_client::responses::Song;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Finds cover image files, such as `cover.jpg`,
+/// in the directory of the song being played.
+///
+/// The cover is a local file, so is only displayed
+/// if an uploader is configured to give it a public URL.
+#[derive(Clone)]
+pub struct LocalProvider {
+    patterns: Vec<Pattern>,
+}
+
+impl LocalProvider {
+    pub fn new(config: &LocalConfig) -> Self {
+        let patterns = config
+            .patterns
+            .iter()
+            .filter_map(|pattern| match Pattern::new(pattern) {
+                Ok(pattern) => Some(pattern),
+                Err(err) => {
+                    warn!("Invalid local cover pattern '{pattern}': {err}");
+                    None
+                }
+            })
+            .collect();
+
+        Self { patterns }
+    }
+
+    /// Finds the cover for the song at `song_url`, relative to `music_dir`.
+    ///
+    /// Returns the file matching the earliest pattern,
+    /// taking the alphabetically first file if a pattern matches several.
+    pub fn find_cover(&self, music_dir: &Path, song_url: &str) -> Option<PathBuf> {
+        // streams have no directory
+        if song_url.contains("://") {
+            return None;
+        }
+
+        let dir = music_dir.join(song_url).parent()?.to_path_buf();
+
+        let mut files = match list_images(&dir) {
+            Ok(files) => files,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                debug!("Song directory '{}' does not exist", dir.display());
+                return None;
+            }
+            Err(err) => {
+                warn!("Failed to read song directory '{}': {err}", dir.display());
+                return None;
+            }
+        };
+
+        files.sort();
+
+        self.patterns.iter().find_map(|pattern| {
+            files
+                .iter()
+                .find(|name| pattern.matches_with(name, MATCH_OPTIONS))
+                .map(|name| dir.join(name))
+        })
+    }
+}
+
+/// Lists the names of image files in a directory.
+fn list_images(dir: &Path) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        let is_image = Path::new(&name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                let ext = ext.to_ascii_lowercase();
+                ext == "jpeg" || IMAGE_EXTENSIONS.contains(&ext.as_str())
+            });
+
+        if is_image {
+            files.push(name);
+        }
+    }
+
+    Ok(files)
+}
+
+impl ArtProvider for LocalProvider {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn find_art<'a>(
+        &'a self,
+        song: &'a Song,
+        music_dir: &'a Path,
+        _artist: &'a str,
+        _album: &'a str,
+    ) -> BoxFuture<'a, http::Result<ArtLookup>> {
+        let provider = self.clone();
+        let music_dir = music_dir.to_path_buf();
+        let song_url = song.url.clone();
+
+        Box::pin(async move {
+            let cover = blocking::run(move || provider.find_cover(&music_dir, &song_url)).await;
+
+            Ok(match cover {
+                Some(path) => {
+                    debug!("Found local cover '{}'", path.display());
+                    ArtLookup::Local(path)
+                }
+                None => ArtLookup::NotFound(None),
+            })
+        })
+    }
+}
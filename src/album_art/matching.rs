@@ -0,0 +1,162 @@
+/// Number of search results to pick a match from,
+/// requested from each provider that searches.
+pub const SEARCH_CANDIDATES: usize = 10;
+
+/// Quotes a value for use as a Lucene phrase in a search query,
+/// so characters such as `:`, `(` or words like `AND` are matched literally.
+pub fn quote_lucene(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+
+    out.push('"');
+    out
+}
+
+/// Normalizes a title or name for comparison:
+/// lowercases it, treats `&` as `and`,
+/// drops punctuation and collapses whitespace.
+pub fn normalize(value: &str) -> String {
+    let value = value.to_lowercase().replace('&', " and ");
+
+    value
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Gets how similar two strings are after normalizing them,
+/// from `0.0` (nothing in common) to `1.0` (identical).
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = normalize(a).chars().collect::<Vec<_>>();
+    let b = normalize(b).chars().collect::<Vec<_>>();
+
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+
+    1.0 - levenshtein(&a, &b) as f64 / longest as f64
+}
+
+/// Number of single-character edits needed to turn `a` into `b`.
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    let mut curr = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        curr[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b.len()]
+}
+
+/// Scores a search result against the artist and album being looked for,
+/// from `0.0` to `1.0`.
+///
+/// `provider_score` is the relevance the search service reported itself
+/// (also from `0.0` to `1.0`), if it reports one.
+/// It is weighed equally with how closely the names match.
+pub fn score_candidate(
+    artist: &str,
+    album: &str,
+    candidate_artist: &str,
+    candidate_album: &str,
+    provider_score: Option<f64>,
+) -> f64 {
+    let name_score =
+        (similarity(artist, candidate_artist) + similarity(album, candidate_album)) / 2.0;
+
+    match provider_score {
+        Some(provider_score) => (provider_score + name_score) / 2.0,
+        None => name_score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The default `min_match_score`.
+    const THRESHOLD: f64 = 0.75;
+
+    #[test]
+    fn quotes_lucene_special_characters() {
+        assert_eq!(quote_lucene("Title: Part (One)"), r#""Title: Part (One)""#);
+        assert_eq!(quote_lucene("Rock AND Roll"), r#""Rock AND Roll""#);
+        assert_eq!(
+            quote_lucene(r#"The "Best" of C:\"#),
+            r#""The \"Best\" of C:\\""#
+        );
+    }
+
+    #[test]
+    fn normalizes_punctuation_and_case() {
+        assert_eq!(
+            normalize("Live: AND (Deluxe Edition)"),
+            "live and deluxe edition"
+        );
+        assert_eq!(normalize("Simon & Garfunkel"), "simon and garfunkel");
+        assert_eq!(normalize(r#"  "Heroes"  "#), "heroes");
+        assert_eq!(normalize("Björk"), "björk");
+        assert_eq!(normalize("?!"), "");
+    }
+
+    #[test]
+    fn similarity_ignores_formatting() {
+        assert_eq!(similarity("Simon & Garfunkel", "simon and garfunkel"), 1.0);
+        assert_eq!(similarity("Title: (Part 1)", "Title Part 1"), 1.0);
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("abc", "xyz"), 0.0);
+
+        let close = similarity(
+            "Sgt. Pepper's Lonely Hearts Club Band",
+            "Sgt Peppers Lonely Hearts Club Band",
+        );
+        assert!(close > 0.95, "{close}");
+    }
+
+    #[test]
+    fn scores_candidates_against_threshold() {
+        let exact = score_candidate(
+            "Queen",
+            "A Night at the Opera",
+            "Queen",
+            "A Night At The Opera",
+            None,
+        );
+        assert_eq!(exact, 1.0);
+
+        // a remaster suffix still matches
+        let remaster = score_candidate(
+            "Queen",
+            "A Night at the Opera",
+            "Queen",
+            "A Night at the Opera (2011 Remaster)",
+            None,
+        );
+        assert!(remaster >= THRESHOLD, "{remaster}");
+
+        // the right album by the wrong artist doesn't
+        let wrong_artist = score_candidate("Queen", "Greatest Hits", "ABBA", "Greatest Hits", None);
+        assert!(wrong_artist < THRESHOLD, "{wrong_artist}");
+
+        // the provider's own score is weighed equally with the names
+        let with_provider = score_candidate("Queen", "Innuendo", "Queen", "Innuendo", Some(0.5));
+        assert_eq!(with_provider, 0.75);
+    }
+}
@@ -0,0 +1,674 @@
+use super::APP_USER_AGENT;
+use super::http::{self, RateLimiter, RetryPolicy};
+use super::matching;
+use super::provider::{ArtLookup, ArtProvider, BoxFuture, MissingRelease};
+use crate::config::{CoverArtSize, MusicbrainzConfig};
+use crate::mpd_conn::try_get_first_tag;
+use mpd_client::responses::Song;
+use mpd_client::tag::Tag;
+use reqwest::header::USER_AGENT;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::debug;
+
+/// MusicBrainz allows an average of one request per second per client.
+/// https://musicbrainz.org/doc/MusicBrainz_API/Rate_Limiting
+const MUSICBRAINZ_REQUESTS_PER_SEC: f64 = 1.0;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct SearchResult {
+    release_groups: Vec<SearchReleaseGroup>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct SearchReleaseGroup {
+    id: String,
+    score: u8,
+    title: String,
+    #[serde(default)]
+    artist_credit: Vec<ArtistCredit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArtistCredit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+}
+
+impl SearchReleaseGroup {
+    /// Gets the full artist credit as displayed, eg `Artist A feat. Artist B`.
+    fn artist(&self) -> String {
+        self.artist_credit
+            .iter()
+            .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseGroup {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Release {
+    id: String,
+    release_group: ReleaseGroup,
+    cover_art_archive: ReleaseCoverArt,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseCoverArt {
+    front: bool,
+}
+
+/// Cover Art Archive's list of images for a release.
+#[derive(Deserialize, Debug)]
+struct CoverArtIndex {
+    images: Vec<CoverArtImage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CoverArtImage {
+    image: String,
+    #[serde(default)]
+    back: bool,
+    #[serde(default)]
+    thumbnails: HashMap<String, String>,
+}
+
+impl CoverArtImage {
+    /// Gets the URL of the image at `size`,
+    /// or the original if there is no thumbnail that size.
+    fn url(&self, size: CoverArtSize) -> &str {
+        thumbnail_key(size)
+            .and_then(|key| self.thumbnails.get(key))
+            .unwrap_or(&self.image)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Type {
+    Release,
+    ReleaseGroup,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Release => "release",
+                Self::ReleaseGroup => "release-group",
+            }
+        )
+    }
+}
+
+/// How the MusicBrainz record for an album was found.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupSource {
+    ReleaseGroupTag,
+    ReleaseTag,
+    Search,
+}
+
+impl Display for LookupSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::ReleaseGroupTag => "release group tag",
+                Self::ReleaseTag => "release tag",
+                Self::Search => "search",
+            }
+        )
+    }
+}
+
+/// MPD (0.24+) exposes the release group ID as `MUSICBRAINZ_RELEASEGROUPID`,
+/// which the client library doesn't have a variant for.
+pub fn release_group_id_tag() -> Tag {
+    Tag::Other("MUSICBRAINZ_RELEASEGROUPID".into())
+}
+
+/// Finds covers on Cover Art Archive,
+/// using MusicBrainz to find the release or release group for an album.
+///
+/// Clones share the same rate limiter.
+#[derive(Clone)]
+pub struct MusicBrainzProvider {
+    client: Client,
+    limiter: Arc<RateLimiter>,
+    retry_policy: RetryPolicy,
+    min_match_score: f64,
+    base_url: String,
+    cover_art_base_url: String,
+    user_agent: String,
+    cover_size: CoverArtSize,
+    fallback_images: bool,
+}
+
+impl MusicBrainzProvider {
+    pub fn new(
+        client: Client,
+        retry_policy: RetryPolicy,
+        min_match_score: f64,
+        config: &MusicbrainzConfig,
+    ) -> Self {
+        Self {
+            client,
+            limiter: Arc::new(RateLimiter::new(1, MUSICBRAINZ_REQUESTS_PER_SEC)),
+            retry_policy,
+            min_match_score,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            cover_art_base_url: config.cover_art_base_url.trim_end_matches('/').to_string(),
+            user_agent: format!(
+                "{APP_USER_AGENT} ( {} )",
+                config
+                    .contact
+                    .as_deref()
+                    .unwrap_or(env!("CARGO_PKG_REPOSITORY"))
+            ),
+            cover_size: config.cover_size,
+            fallback_images: config.fallback_images,
+        }
+    }
+
+    /// Sends a GET request to `path` on the MusicBrainz API,
+    /// respecting its rate limit.
+    async fn musicbrainz_get(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> http::Result<reqwest::Response> {
+        let url = format!("{}/{path}", self.base_url);
+
+        http::send(
+            self.client
+                .get(url)
+                .header(USER_AGENT, &self.user_agent)
+                .query(query),
+            Some(&self.limiter),
+            &self.retry_policy,
+        )
+        .await
+    }
+
+    /// Builds a request to `path` on Cover Art Archive.
+    fn cover_art_request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}/{path}", self.cover_art_base_url))
+            .header(USER_AGENT, &self.user_agent)
+    }
+
+    /// Looks up a release by its UUID on MusicBrainz.
+    /// If the release has a cover, returns the ID of that record.
+    /// If not, returns the ID of its release group.
+    ///
+    /// Returns `None` if the release does not exist.
+    async fn get_record_id(&self, release_id: &str) -> http::Result<Option<(String, Type)>> {
+        let response = self
+            .musicbrainz_get(
+                &format!("release/{release_id}"),
+                &[("inc", "release-groups")],
+            )
+            .await?;
+
+        match response.status() {
+            StatusCode::OK => {
+                let release = response
+                    .json::<Release>()
+                    .await
+                    .map_err(http::Error::Decode)?;
+
+                if release.cover_art_archive.front {
+                    Ok(Some((release.id, Type::Release)))
+                } else {
+                    Ok(Some((release.release_group.id, Type::ReleaseGroup)))
+                }
+            }
+            // MusicBrainz responds with 400 for malformed IDs
+            StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST => Ok(None),
+            status => Err(http::Error::Status(status)),
+        }
+    }
+
+    /// Searches for a release group on MusicBrainz.
+    ///
+    /// Each result is scored by MusicBrainz's own relevance
+    /// and how closely its artist and title match,
+    /// returning the ID of the best one if it scores at least `min_match_score`.
+    async fn find_release_group_id(
+        &self,
+        artist: &str,
+        album: &str,
+    ) -> http::Result<Option<String>> {
+        let query = format!(
+            "artist:{} AND releasegroup:{}",
+            matching::quote_lucene(artist),
+            matching::quote_lucene(album)
+        );
+        let limit = matching::SEARCH_CANDIDATES.to_string();
+
+        let response = self
+            .musicbrainz_get("release-group/", &[("query", &query), ("limit", &limit)])
+            .await?;
+
+        if response.status() != StatusCode::OK {
+            return Err(http::Error::Status(response.status()));
+        }
+
+        let response = response
+            .json::<SearchResult>()
+            .await
+            .map_err(http::Error::Decode)?;
+
+        let best = response
+            .release_groups
+            .into_iter()
+            .map(|rg| {
+                let score = matching::score_candidate(
+                    artist,
+                    album,
+                    &rg.artist(),
+                    &rg.title,
+                    Some(f64::from(rg.score) / 100.0),
+                );
+                (rg, score)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        Ok(match best {
+            Some((rg, score)) if score >= self.min_match_score => {
+                debug!(
+                    "Matched '{artist} - {album}' to '{} - {}' ({}) with score {score:.2}",
+                    rg.artist(),
+                    rg.title,
+                    rg.id
+                );
+                Some(rg.id)
+            }
+            Some((rg, score)) => {
+                debug!(
+                    "Best match for '{artist} - {album}' was '{} - {}' with score {score:.2}, below threshold",
+                    rg.artist(),
+                    rg.title
+                );
+                None
+            }
+            None => None,
+        })
+    }
+
+    /// Checks whether Cover Art Archive has an image at `path`.
+    async fn cover_exists(&self, path: &str) -> http::Result<bool> {
+        let request = self.cover_art_request(Method::HEAD, path);
+        let response = http::send(request, None, &self.retry_policy).await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(http::Error::Status(status)),
+        }
+    }
+
+    /// Finds the cover for a record at the configured size.
+    ///
+    /// If it has no front cover and `fallback_images` is set,
+    /// another of its images is used instead,
+    /// or one of `release_id`'s (the release the song is tagged with).
+    async fn find_cover(
+        &self,
+        record_type: Type,
+        id: &str,
+        release_id: Option<&str>,
+    ) -> http::Result<Option<String>> {
+        let path = cover_path(record_type, id, self.cover_size);
+
+        if self.cover_exists(&path).await? {
+            return Ok(Some(format!("{}/{path}", self.cover_art_base_url)));
+        }
+
+        if !self.fallback_images {
+            return Ok(None);
+        }
+
+        let mut records = vec![(record_type, id)];
+        if let Some(release_id) = release_id.filter(|release_id| *release_id != id) {
+            records.push((Type::Release, release_id));
+        }
+
+        for (record_type, id) in records {
+            if let Some(url) = self.find_other_image(record_type, id).await? {
+                debug!("No front cover for {record_type} {id}, using {url}");
+                return Ok(Some(url));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Picks an image from a record's Cover Art Archive index,
+    /// preferring the back cover, otherwise the first image.
+    ///
+    /// Release groups only have an index if one of their releases has a front cover.
+    async fn find_other_image(&self, record_type: Type, id: &str) -> http::Result<Option<String>> {
+        let request = self.cover_art_request(Method::GET, &format!("{record_type}/{id}"));
+        let response = http::send(request, None, &self.retry_policy).await?;
+
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND | StatusCode::BAD_REQUEST => return Ok(None),
+            status => return Err(http::Error::Status(status)),
+        }
+
+        let index = response
+            .json::<CoverArtIndex>()
+            .await
+            .map_err(http::Error::Decode)?;
+
+        let image = index
+            .images
+            .iter()
+            .find(|image| image.back)
+            .or(index.images.first());
+
+        Ok(image.map(|image| image.url(self.cover_size).to_string()))
+    }
+
+    /// Finds the MusicBrainz record to fetch cover art for,
+    /// trying in order:
+    ///
+    /// - The release group ID tag, which needs no requests to MusicBrainz
+    /// - The release ID tag
+    /// - Searching by artist and album
+    async fn find_record(
+        &self,
+        release_group_id: Option<&str>,
+        release_id: Option<&str>,
+        artist: &str,
+        album: &str,
+    ) -> http::Result<Option<(String, Type, LookupSource)>> {
+        if let Some(id) = release_group_id {
+            return Ok(Some((
+                id.to_string(),
+                Type::ReleaseGroup,
+                LookupSource::ReleaseGroupTag,
+            )));
+        }
+
+        if let Some(release_id) = release_id {
+            if let Some((id, record_type)) = self.get_record_id(release_id).await? {
+                return Ok(Some((id, record_type, LookupSource::ReleaseTag)));
+            }
+
+            debug!("Release {release_id} not found on MusicBrainz, falling back to search");
+        }
+
+        Ok(self
+            .find_release_group_id(artist, album)
+            .await?
+            .map(|id| (id, Type::ReleaseGroup, LookupSource::Search)))
+    }
+
+    /// Gets the cover for a known release or release group, skipping the search.
+    /// A release without a cover falls back to its release group's.
+    ///
+    /// Returns `None` if the record doesn't exist or has no cover.
+    pub async fn find_record_art(
+        &self,
+        id: &str,
+        record_type: Type,
+    ) -> http::Result<Option<String>> {
+        let (record_id, record_type, release_id) = match record_type {
+            Type::Release => match self.get_record_id(id).await? {
+                Some((record_id, record_type)) => (record_id, record_type, Some(id)),
+                None => return Ok(None),
+            },
+            Type::ReleaseGroup => (id.to_string(), record_type, None),
+        };
+
+        self.find_cover(record_type, &record_id, release_id).await
+    }
+
+    /// Gets the cover for an album without a song to read tags from,
+    /// by its release group or release ID if known,
+    /// otherwise by searching for the artist and album.
+    ///
+    /// Returns the ID of the record the cover was found for, and the cover's URL.
+    pub async fn find_album_art(
+        &self,
+        release_group_id: Option<&str>,
+        release_id: Option<&str>,
+        artist: &str,
+        album: &str,
+    ) -> http::Result<Option<(String, String)>> {
+        let Some((id, record_type, _)) = self
+            .find_record(release_group_id, release_id, artist, album)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .find_cover(record_type, &id, release_id)
+            .await?
+            .map(|url| (id, url)))
+    }
+
+    async fn lookup(&self, song: &Song, artist: &str, album: &str) -> http::Result<ArtLookup> {
+        let tags = &song.tags;
+
+        self.lookup_ids(
+            try_get_first_tag(tags.get(&release_group_id_tag())),
+            try_get_first_tag(tags.get(&Tag::MusicBrainzReleaseId)),
+            artist,
+            album,
+        )
+        .await
+    }
+
+    /// Looks up the cover for an album by the MusicBrainz IDs the song is tagged with, if any.
+    async fn lookup_ids(
+        &self,
+        release_group_id: Option<&str>,
+        release_id: Option<&str>,
+        artist: &str,
+        album: &str,
+    ) -> http::Result<ArtLookup> {
+        let Some((id, record_type, source)) = self
+            .find_record(release_group_id, release_id, artist, album)
+            .await?
+        else {
+            return Ok(ArtLookup::NotFound(Some(MissingRelease {
+                reason: "no_mb_match",
+                mbid: None,
+                release_group_id: None,
+                lookup: None,
+            })));
+        };
+
+        debug!("Resolved '{artist} - {album}' to {record_type} {id} via {source}");
+
+        if let Some(url) = self.find_cover(record_type, &id, release_id).await? {
+            Ok(ArtLookup::Found {
+                url,
+                record: Some((id, record_type)),
+            })
+        } else {
+            Ok(ArtLookup::NotFound(Some(MissingRelease {
+                reason: "missing_caa",
+                mbid: release_id.map(ToString::to_string),
+                release_group_id: matches!(record_type, Type::ReleaseGroup).then_some(id),
+                lookup: Some(source),
+            })))
+        }
+    }
+}
+
+/// Gets the path to a record's front cover on Cover Art Archive.
+fn cover_path(record_type: Type, id: &str, size: CoverArtSize) -> String {
+    let suffix = thumbnail_key(size).map_or(String::new(), |key| format!("-{key}"));
+    format!("{record_type}/{id}/front{suffix}")
+}
+
+/// Gets the name Cover Art Archive uses for a thumbnail size,
+/// or `None` for the original image.
+fn thumbnail_key(size: CoverArtSize) -> Option<&'static str> {
+    match size {
+        CoverArtSize::Small => Some("250"),
+        CoverArtSize::Large => Some("500"),
+        CoverArtSize::ExtraLarge => Some("1200"),
+        CoverArtSize::Original => None,
+    }
+}
+
+impl ArtProvider for MusicBrainzProvider {
+    fn name(&self) -> &'static str {
+        "musicbrainz"
+    }
+
+    fn find_art<'a>(
+        &'a self,
+        song: &'a Song,
+        _music_dir: &'a Path,
+        artist: &'a str,
+        album: &'a str,
+    ) -> BoxFuture<'a, http::Result<ArtLookup>> {
+        Box::pin(self.lookup(song, artist, album))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::album_art::test_server::{Request, TestServer};
+    use reqwest::Url;
+    use serde_json::json;
+    use std::time::Duration;
+
+    const ARTIST: &str = "AC/DC";
+    const ALBUM: &str = r#"Live: "At" Donington (AND More)"#;
+
+    fn provider(server: &TestServer) -> MusicBrainzProvider {
+        MusicBrainzProvider::new(
+            Client::new(),
+            RetryPolicy {
+                max_retries: 0,
+                base_delay: Duration::ZERO,
+            },
+            0.75,
+            &MusicbrainzConfig {
+                base_url: format!("{}/ws/2", server.url),
+                cover_art_base_url: server.url.clone(),
+                ..MusicbrainzConfig::default()
+            },
+        )
+    }
+
+    /// Responds to searches with a close match and a worse one MusicBrainz ranks higher,
+    /// and to cover checks with `cover_status`.
+    fn respond(request: &Request, cover_status: u16) -> (u16, String) {
+        if request.path.starts_with("/ws/2/release-group/?") {
+            let results = json!({
+                "release-groups": [
+                    {
+                        "id": "wrong-id",
+                        "score": 100,
+                        "title": "Donington",
+                        "artist-credit": [{ "name": "AC/DC Tribute Band" }],
+                    },
+                    {
+                        "id": "right-id",
+                        "score": 90,
+                        "title": ALBUM,
+                        "artist-credit": [{ "name": "AC/DC" }],
+                    },
+                ]
+            });
+            (200, results.to_string())
+        } else if request.method == "HEAD" {
+            (cover_status, String::new())
+        } else {
+            (404, String::new())
+        }
+    }
+
+    fn search_query(request: &Request) -> HashMap<String, String> {
+        Url::parse(&format!("http://localhost{}", request.path))
+            .expect("path to be a valid URL")
+            .query_pairs()
+            .into_owned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn searches_and_picks_best_release_group() {
+        let server = TestServer::start(|request| respond(request, 200));
+
+        let lookup = provider(&server)
+            .lookup_ids(None, None, ARTIST, ALBUM)
+            .await
+            .expect("lookup to succeed");
+
+        match lookup {
+            ArtLookup::Found { url, record } => {
+                assert_eq!(
+                    url,
+                    format!("{}/release-group/right-id/front-250", server.url)
+                );
+                assert!(matches!(
+                    record,
+                    Some((id, Type::ReleaseGroup)) if id == "right-id"
+                ));
+            }
+            lookup => panic!("expected a cover, got {lookup:?}"),
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+
+        let query = search_query(&requests[0]);
+        assert_eq!(
+            query["query"],
+            r#"artist:"AC/DC" AND releasegroup:"Live: \"At\" Donington (AND More)""#
+        );
+        assert_eq!(query["limit"], "10");
+
+        assert_eq!(requests[1].method, "HEAD");
+        assert_eq!(requests[1].path, "/release-group/right-id/front-250");
+    }
+
+    #[tokio::test]
+    async fn missing_cover_is_queued_as_missing_caa() {
+        let server = TestServer::start(|request| respond(request, 404));
+
+        let lookup = provider(&server)
+            .lookup_ids(None, None, ARTIST, ALBUM)
+            .await
+            .expect("lookup to succeed");
+
+        match lookup {
+            ArtLookup::NotFound(Some(missing)) => {
+                assert_eq!(missing.reason, "missing_caa");
+                assert_eq!(missing.mbid, None);
+                assert_eq!(missing.release_group_id.as_deref(), Some("right-id"));
+                assert!(matches!(missing.lookup, Some(LookupSource::Search)));
+            }
+            lookup => panic!("expected a missing release, got {lookup:?}"),
+        }
+    }
+}
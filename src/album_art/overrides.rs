@@ -0,0 +1,365 @@
+use super::matching;
+use super::musicbrainz::{Type, release_group_id_tag};
+use crate::mpd_conn::try_get_first_tag;
+use glob::{MatchOptions, Pattern};
+use mpd_client::responses::Song;
+use mpd_client::tag::Tag;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
+
+/// Least time between checking the file for changes.
+/// Lookups run on the async runtime, so shouldn't touch the disk every time.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Deserialize, Debug, Default)]
+struct OverridesFile {
+    #[serde(default, rename = "override")]
+    overrides: Vec<OverrideEntry>,
+}
+
+/// A single entry as written in the file.
+///
+/// Any of `artist`, `album`, `mbid` and `path` select which songs it applies to,
+/// all of which must match.
+/// Exactly one of `release`, `release_group`, `url` and `no_art` sets the cover.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct OverrideEntry {
+    artist: Option<String>,
+    album: Option<String>,
+    /// Release or release group ID the song is tagged with.
+    mbid: Option<String>,
+    /// Glob matched against the song's path, relative to the music directory.
+    path: Option<String>,
+
+    release: Option<String>,
+    release_group: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    no_art: bool,
+}
+
+/// The cover an override sets.
+#[derive(Debug, Clone)]
+pub enum OverrideArt {
+    /// Use the cover of this MusicBrainz record from Cover Art Archive.
+    Record(Type, String),
+    /// Use this image.
+    Url(String),
+    /// Show no cover.
+    NoArt,
+}
+
+#[derive(Debug)]
+struct Override {
+    artist: Option<String>,
+    album: Option<String>,
+    mbid: Option<String>,
+    path: Option<Pattern>,
+    art: OverrideArt,
+}
+
+impl TryFrom<OverrideEntry> for Override {
+    type Error = String;
+
+    fn try_from(entry: OverrideEntry) -> Result<Self, Self::Error> {
+        let art = match (entry.release, entry.release_group, entry.url, entry.no_art) {
+            (Some(id), None, None, false) => OverrideArt::Record(Type::Release, id),
+            (None, Some(id), None, false) => OverrideArt::Record(Type::ReleaseGroup, id),
+            (None, None, Some(url), false) => OverrideArt::Url(url),
+            (None, None, None, true) => OverrideArt::NoArt,
+            _ => {
+                return Err(
+                    "exactly one of `release`, `release_group`, `url` or `no_art` must be set"
+                        .to_string(),
+                );
+            }
+        };
+
+        if entry.artist.is_none()
+            && entry.album.is_none()
+            && entry.mbid.is_none()
+            && entry.path.is_none()
+        {
+            return Err(
+                "at least one of `artist`, `album`, `mbid` or `path` must be set".to_string(),
+            );
+        }
+
+        let path = entry
+            .path
+            .map(|path| Pattern::new(&path).map_err(|err| format!("invalid path '{path}': {err}")))
+            .transpose()?;
+
+        Ok(Self {
+            artist: entry.artist.as_deref().map(matching::normalize),
+            album: entry.album.as_deref().map(matching::normalize),
+            mbid: entry.mbid.map(|mbid| mbid.to_ascii_lowercase()),
+            path,
+            art,
+        })
+    }
+}
+
+impl Override {
+    /// Whether the override applies to the song at `url` with `tags`.
+    fn matches(
+        &self,
+        url: &str,
+        tags: &HashMap<Tag, Vec<String>>,
+        artist: &str,
+        album: &str,
+    ) -> bool {
+        self.artist
+            .as_ref()
+            .is_none_or(|expected| *expected == matching::normalize(artist))
+            && self
+                .album
+                .as_ref()
+                .is_none_or(|expected| *expected == matching::normalize(album))
+            && self.mbid.as_ref().is_none_or(|expected| {
+                [Tag::MusicBrainzReleaseId, release_group_id_tag()]
+                    .iter()
+                    .filter_map(|tag| try_get_first_tag(tags.get(tag)))
+                    .any(|mbid| mbid.eq_ignore_ascii_case(expected))
+            })
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches_with(url, MATCH_OPTIONS))
+    }
+}
+
+/// Manual overrides for albums' covers,
+/// for when providers pick the wrong album or have a bad cover.
+///
+/// The file is checked for changes on lookups, at most every `CHECK_INTERVAL`,
+/// and reloaded if edited.
+pub struct Overrides {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_checked: Option<Instant>,
+    overrides: Vec<Override>,
+    /// Covers found for `Record` overrides, by record ID.
+    /// Cleared on reload.
+    resolved: HashMap<String, Option<String>>,
+}
+
+impl Overrides {
+    pub fn load(path: PathBuf) -> Self {
+        let mut overrides = Self {
+            path,
+            modified: None,
+            last_checked: None,
+            overrides: Vec::new(),
+            resolved: HashMap::new(),
+        };
+
+        overrides.reload_if_changed();
+        overrides
+    }
+
+    /// Gets the override for an album, if there is one.
+    /// If several apply, the first in the file wins.
+    pub fn get(&mut self, song: &Song, artist: &str, album: &str) -> Option<OverrideArt> {
+        self.reload_if_changed();
+
+        self.overrides
+            .iter()
+            .find(|entry| entry.matches(&song.url, &song.tags, artist, album))
+            .map(|entry| entry.art.clone())
+    }
+
+    /// Gets the previously found cover for a `Record` override.
+    pub fn get_resolved(&self, id: &str) -> Option<Option<String>> {
+        self.resolved.get(id).cloned()
+    }
+
+    pub fn insert_resolved(&mut self, id: String, url: Option<String>) {
+        self.resolved.insert(id, url);
+    }
+
+    fn reload_if_changed(&mut self) {
+        if self
+            .last_checked
+            .is_some_and(|checked| checked.elapsed() < CHECK_INTERVAL)
+        {
+            return;
+        }
+
+        self.last_checked = Some(Instant::now());
+
+        let modified = match fs::metadata(&self.path).and_then(|meta| meta.modified()) {
+            Ok(modified) => Some(modified),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!(
+                    "Failed to read overrides file '{}': {err}",
+                    self.path.display()
+                );
+                return;
+            }
+        };
+
+        if modified == self.modified {
+            return;
+        }
+
+        self.modified = modified;
+        self.resolved.clear();
+
+        if modified.is_none() {
+            debug!("Overrides file '{}' removed", self.path.display());
+            self.overrides.clear();
+            return;
+        }
+
+        // a file that fails to parse keeps the previous overrides,
+        // so a half-finished edit doesn't drop them all
+        match read_file(&self.path) {
+            Ok(file) => {
+                self.overrides = file
+                    .overrides
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, entry)| match Override::try_from(entry) {
+                        Ok(entry) => Some(entry),
+                        Err(err) => {
+                            warn!("Skipping override #{}: {err}", i + 1);
+                            None
+                        }
+                    })
+                    .collect();
+
+                info!(
+                    "Loaded {} album art overrides from '{}'",
+                    self.overrides.len(),
+                    self.path.display()
+                );
+            }
+            Err(err) => error!(
+                "Failed to parse overrides file '{}': {err}",
+                self.path.display()
+            ),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<OverridesFile, String> {
+    let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&contents).map_err(|err| err.to_string())
+    } else {
+        toml::from_str(&contents).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Result<Override, String> {
+        let mut file = toml::from_str::<OverridesFile>(toml).expect("valid TOML");
+        Override::try_from(file.overrides.remove(0))
+    }
+
+    fn tags(tags: &[(Tag, &str)]) -> HashMap<Tag, Vec<String>> {
+        tags.iter()
+            .map(|(tag, value)| (tag.clone(), vec![value.to_string()]))
+            .collect()
+    }
+
+    #[test]
+    fn requires_exactly_one_cover() {
+        for art in [
+            r#"release = "id""#,
+            r#"release_group = "id""#,
+            r#"url = "https://example.com/a.jpg""#,
+            "no_art = true",
+        ] {
+            let entry = parse(&format!("[[override]]\nartist = \"A\"\n{art}"));
+            assert!(entry.is_ok(), "{art}: {entry:?}");
+        }
+
+        for art in [
+            "",
+            "no_art = false",
+            "release = \"id\"\nurl = \"https://example.com/a.jpg\"",
+            "release_group = \"id\"\nno_art = true",
+        ] {
+            let err =
+                parse(&format!("[[override]]\nartist = \"A\"\n{art}")).expect_err("to be rejected");
+            assert!(err.contains("exactly one of"), "{art}: {err}");
+        }
+    }
+
+    #[test]
+    fn requires_at_least_one_selector() {
+        let err = parse("[[override]]\nno_art = true").expect_err("to be rejected");
+        assert!(err.contains("at least one of"), "{err}");
+
+        let err = parse("[[override]]\npath = \"[\"\nno_art = true").expect_err("to be rejected");
+        assert!(err.contains("invalid path"), "{err}");
+    }
+
+    #[test]
+    fn matches_normalized_artist_and_album() {
+        let entry = parse(
+            "[[override]]\nartist = \"Simon & Garfunkel\"\nalbum = \"Bookends (Remastered)\"\nno_art = true",
+        )
+        .expect("valid override");
+        let tags = tags(&[]);
+
+        assert!(entry.matches(
+            "a.flac",
+            &tags,
+            "simon and garfunkel",
+            "Bookends: Remastered"
+        ));
+        assert!(!entry.matches("a.flac", &tags, "Simon & Garfunkel", "Bookends"));
+        assert!(!entry.matches("a.flac", &tags, "Paul Simon", "Bookends (Remastered)"));
+    }
+
+    #[test]
+    fn matches_mbid_case_insensitively_against_either_tag() {
+        let entry =
+            parse("[[override]]\nmbid = \"ABCDEF-1234\"\nno_art = true").expect("valid override");
+
+        let release = tags(&[(Tag::MusicBrainzReleaseId, "abcdef-1234")]);
+        let release_group = tags(&[
+            (Tag::MusicBrainzReleaseId, "other"),
+            (release_group_id_tag(), "AbCdEf-1234"),
+        ]);
+        let neither = tags(&[(Tag::MusicBrainzReleaseId, "other")]);
+
+        assert!(entry.matches("a.flac", &release, "A", "B"));
+        assert!(entry.matches("a.flac", &release_group, "A", "B"));
+        assert!(!entry.matches("a.flac", &neither, "A", "B"));
+        assert!(!entry.matches("a.flac", &tags(&[]), "A", "B"));
+    }
+
+    #[test]
+    fn matches_path_glob() {
+        let entry = parse("[[override]]\npath = \"Bootlegs/*/*.flac\"\nno_art = true")
+            .expect("valid override");
+        let tags = tags(&[]);
+
+        assert!(entry.matches("Bootlegs/1999 Live/01.flac", &tags, "A", "B"));
+        // `*` doesn't cross directories
+        assert!(!entry.matches("Bootlegs/1999/Disc 1/01.flac", &tags, "A", "B"));
+        assert!(!entry.matches("Albums/1999 Live/01.flac", &tags, "A", "B"));
+        assert!(!entry.matches("bootlegs/1999 Live/01.flac", &tags, "A", "B"));
+    }
+}
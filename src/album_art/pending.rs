@@ -0,0 +1,828 @@
+//! Queue of releases missing from MusicBrainz or Cover Art Archive,
+//! written to disk so they can be added later.
+//!
+//! Each release has a `<key>.json` file, holding the release's details
+//! and a `tracks` array of every track from it that has been played,
+//! alongside its cover as `<key>.<ext>` if one could be extracted.
+//!
+//! Files are written to a temporary file and renamed into place,
+//! so a crash can't leave a half-written entry behind.
+
+use super::musicbrainz::LookupSource;
+use super::provider::MissingRelease;
+use super::{atomic, blocking, extract};
+use crate::mpd_conn::try_get_first_tag;
+use chrono::{DateTime, Utc};
+use mpd_client::Client as MpdClient;
+use mpd_client::responses::Song;
+use mpd_client::tag::Tag;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+/// Version of the entry format written.
+///
+/// - 0: one file per track, before versions were recorded
+/// - 1: one file per release with a `tracks` array, before versions were recorded
+/// - 2: adds `schema_version`
+const SCHEMA_VERSION: u32 = 2;
+
+/// Subdirectory of the queue resolved entries are moved to.
+const RESOLVED_DIR: &str = "resolved";
+
+/// A release in the queue, as written to its file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingEntry {
+    #[serde(default)]
+    pub schema_version: u32,
+    /// - `missing_caa`: MusicBrainz has the release, but Cover Art Archive has no art
+    /// - `no_mb_match`: MusicBrainz couldn't find the release
+    pub reason: String,
+    /// Release ID the tracks are tagged with.
+    pub mbid: Option<String>,
+    /// Release group the cover was looked for on, if MusicBrainz found one.
+    #[serde(default)]
+    pub release_group_id: Option<String>,
+    /// How the MusicBrainz record was found, if it was.
+    pub lookup: Option<LookupSource>,
+    /// Album artist, or artist.
+    pub artist: String,
+    pub album: String,
+    pub date: String,
+    pub added_at: DateTime<Utc>,
+    /// Tracks played from the release, in disc and track order.
+    pub tracks: Vec<PendingTrack>,
+    /// Set once the release has been added, when moved to the resolved directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<Resolution>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingTrack {
+    pub title: String,
+    pub artist: String,
+    pub disc: String,
+    pub trackno: String,
+    pub duration_secs: u64,
+    pub source_path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Resolution {
+    pub mbid: Option<String>,
+    /// URL of the cover found.
+    pub url: Option<String>,
+    pub resolved_at: DateTime<Utc>,
+}
+
+/// An entry written before versions were recorded,
+/// when each track had its own file.
+#[derive(Deserialize)]
+struct PerTrackEntry {
+    reason: String,
+    mbid: Option<String>,
+    #[serde(default)]
+    lookup: Option<LookupSource>,
+    artist: String,
+    album: String,
+    title: String,
+    trackno: String,
+    date: String,
+    duration_secs: u64,
+    source_path: String,
+    added_at: DateTime<Utc>,
+}
+
+impl From<PerTrackEntry> for PendingEntry {
+    fn from(entry: PerTrackEntry) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            reason: entry.reason,
+            mbid: entry.mbid,
+            release_group_id: None,
+            lookup: entry.lookup,
+            tracks: vec![PendingTrack {
+                title: entry.title,
+                artist: entry.artist.clone(),
+                disc: String::new(),
+                trackno: entry.trackno,
+                duration_secs: entry.duration_secs,
+                source_path: entry.source_path,
+            }],
+            artist: entry.artist,
+            album: entry.album,
+            date: entry.date,
+            added_at: entry.added_at,
+            resolved: None,
+        }
+    }
+}
+
+/// An entry read from the queue.
+pub struct QueuedEntry {
+    pub key: String,
+    pub entry: PendingEntry,
+    /// Path to the release's cover, if one was saved.
+    pub cover: Option<PathBuf>,
+}
+
+/// Gets the key a song's release is queued under:
+/// its release MBID if tagged,
+/// otherwise `nombid_<album artist>_<album>_<date>`.
+pub fn entry_key(song: &Song) -> String {
+    tags_key(&song.tags)
+}
+
+fn tags_key(tags: &HashMap<Tag, Vec<String>>) -> String {
+    let artist = try_get_first_tag(tags.get(&Tag::AlbumArtist))
+        .or(try_get_first_tag(tags.get(&Tag::Artist)))
+        .unwrap_or_default();
+
+    release_key(
+        try_get_first_tag(tags.get(&Tag::MusicBrainzReleaseId)),
+        artist,
+        try_get_first_tag(tags.get(&Tag::Album)).unwrap_or_default(),
+        try_get_first_tag(tags.get(&Tag::Date)),
+    )
+}
+
+fn release_key(mbid: Option<&str>, artist: &str, album: &str, date: Option<&str>) -> String {
+    if let Some(mbid) = mbid {
+        return mbid.to_string();
+    }
+
+    let mut key = format!(
+        "nombid_{}_{}",
+        sanitize_for_filename(artist),
+        sanitize_for_filename(album)
+    );
+
+    if let Some(date) = date {
+        key.push('_');
+        key.push_str(&sanitize_for_filename(date));
+    }
+
+    key
+}
+
+fn sanitize_for_filename(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c);
+        } else if c.is_whitespace() || c == '-' || c == '_' {
+            out.push('_');
+        }
+    }
+    if out.is_empty() {
+        "unknown".to_string()
+    } else {
+        out
+    }
+}
+
+/// Adds the release `song` is on to the queue, or adds the song to its existing entry.
+///
+/// The cover is saved alongside the entry the first time one can be found.
+/// If `local_cover` is set, it is copied into the queue
+/// rather than extracting the cover from the song.
+///
+/// Returns the path to the cover saved in the queue, if there is one.
+#[allow(clippy::too_many_arguments)]
+pub async fn queue_release(
+    mpd: &MpdClient,
+    song: &Song,
+    missing: &MissingRelease,
+    local_cover: Option<&Path>,
+    music_root: &Path,
+    base_dir: &Path,
+    embedded_fallback: bool,
+) -> Option<PathBuf> {
+    let key = entry_key(song);
+
+    let saved_cover = {
+        let (song, missing, key) = (song.clone(), missing.clone(), key.clone());
+        let (music_root, base_dir) = (music_root.to_path_buf(), base_dir.to_path_buf());
+
+        blocking::run(move || add_song(&song, &missing, &music_root, &base_dir, &key)).await
+    };
+
+    match saved_cover {
+        Ok(Some(cover_path)) => return Some(cover_path),
+        Ok(None) => {}
+        Err(err) => {
+            warn!("Failed to write pending entry '{key}': {err}");
+            return None;
+        }
+    }
+
+    let base_dir = base_dir.to_path_buf();
+
+    match local_cover {
+        Some(local_cover) => extract::copy_cover(local_cover.to_path_buf(), base_dir, key).await,
+        None => {
+            let audio_path = music_root.join(&song.url);
+            extract::extract_cover(mpd, song, audio_path, base_dir, key, embedded_fallback).await
+        }
+    }
+}
+
+/// Adds `song` to its release's entry under `key`,
+/// returning the release's cover if one has already been saved.
+fn add_song(
+    song: &Song,
+    missing: &MissingRelease,
+    music_root: &Path,
+    base_dir: &Path,
+    key: &str,
+) -> io::Result<Option<PathBuf>> {
+    fs::create_dir_all(base_dir)?;
+    add_to_entry(
+        &base_dir.join(format!("{key}.json")),
+        new_entry(&song.tags, missing),
+        new_track(song, music_root),
+    )?;
+
+    Ok(extract::find_extracted_cover(base_dir, key))
+}
+
+/// Adds `track` to the entry at `json_path`,
+/// creating it from `entry` if there isn't one.
+fn add_to_entry(json_path: &Path, mut entry: PendingEntry, track: PendingTrack) -> io::Result<()> {
+    if let Some(existing) = read_entry(json_path)? {
+        return add_to_existing(json_path, existing, track);
+    }
+
+    insert_track(&mut entry, track.clone());
+
+    match create_entry(json_path, &entry) {
+        // written by something else since it was checked for,
+        // so add to that rather than replacing it
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => match read_entry(json_path)? {
+            Some(existing) => add_to_existing(json_path, existing, track),
+            None => write_entry(json_path, &entry),
+        },
+        res => res,
+    }
+}
+
+fn add_to_existing(
+    json_path: &Path,
+    mut entry: PendingEntry,
+    track: PendingTrack,
+) -> io::Result<()> {
+    if insert_track(&mut entry, track) {
+        write_entry(json_path, &entry)
+    } else {
+        Ok(())
+    }
+}
+
+/// Adds `song` to its release's entry, if the release is already queued.
+///
+/// Album art lookups are cached per album,
+/// so this is what records the rest of an album's tracks once the first is queued.
+pub async fn record_track(song: &Song, music_root: &Path, base_dir: &Path) {
+    let json_path = base_dir.join(format!("{}.json", entry_key(song)));
+    let track = new_track(song, music_root);
+
+    blocking::run(move || {
+        let res = match read_entry(&json_path) {
+            Ok(Some(entry)) => add_to_existing(&json_path, entry, track),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = res {
+            warn!(
+                "Failed to update pending entry '{}': {err}",
+                json_path.display()
+            );
+        }
+    })
+    .await;
+}
+
+fn new_entry(tags: &HashMap<Tag, Vec<String>>, missing: &MissingRelease) -> PendingEntry {
+    let artist = try_get_first_tag(tags.get(&Tag::AlbumArtist))
+        .or(try_get_first_tag(tags.get(&Tag::Artist)))
+        .unwrap_or_default();
+    let album = try_get_first_tag(tags.get(&Tag::Album)).unwrap_or_default();
+    let date = try_get_first_tag(tags.get(&Tag::Date)).unwrap_or_default();
+
+    PendingEntry {
+        schema_version: SCHEMA_VERSION,
+        reason: missing.reason.to_string(),
+        mbid: missing.mbid.clone(),
+        release_group_id: missing.release_group_id.clone(),
+        lookup: missing.lookup,
+        artist: artist.to_string(),
+        album: album.to_string(),
+        date: date.to_string(),
+        added_at: Utc::now(),
+        tracks: Vec::new(),
+        resolved: None,
+    }
+}
+
+/// Gets the track recorded in an entry for a song.
+fn new_track(song: &Song, music_root: &Path) -> PendingTrack {
+    let get_tag = |tag| {
+        try_get_first_tag(song.tags.get(&tag))
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    PendingTrack {
+        title: get_tag(Tag::Title),
+        artist: get_tag(Tag::Artist),
+        disc: get_tag(Tag::Disc),
+        trackno: get_tag(Tag::Track),
+        duration_secs: song.duration.map(|d| d.as_secs()).unwrap_or(0),
+        source_path: music_root.join(&song.url).to_string_lossy().to_string(),
+    }
+}
+
+/// Inserts a track, keeping the tracks in disc and track order.
+///
+/// Returns `false` if there is already a track with the same path.
+fn insert_track(entry: &mut PendingEntry, track: PendingTrack) -> bool {
+    if entry
+        .tracks
+        .iter()
+        .any(|existing| existing.source_path == track.source_path)
+    {
+        return false;
+    }
+
+    debug!("Adding '{}' to pending entry", track.title);
+
+    entry.tracks.push(track);
+    entry
+        .tracks
+        .sort_by_key(|track| (track_number(&track.disc), track_number(&track.trackno)));
+
+    true
+}
+
+/// Parses a disc or track number tag, which may be written as eg `3/12`.
+pub(super) fn track_number(value: &str) -> u32 {
+    value
+        .split('/')
+        .next()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn read_entry(path: &Path) -> io::Result<Option<PendingEntry>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => parse_entry(&contents)
+            .map(|(entry, _)| Some(entry))
+            .map_err(io::Error::other),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Parses an entry written by any version,
+/// returning it along with the version it was written as.
+fn parse_entry(contents: &str) -> Result<(PendingEntry, u32), String> {
+    let value = serde_json::from_str::<Value>(contents).map_err(|err| err.to_string())?;
+
+    let version = match value.get("schema_version").and_then(Value::as_u64) {
+        Some(version) => u32::try_from(version).unwrap_or(u32::MAX),
+        None if value.get("tracks").is_some() => 1,
+        None => 0,
+    };
+
+    // refuse rather than risk dropping fields when it's written back
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "written by a newer version (schema version {version})"
+        ));
+    }
+
+    let mut entry = if version == 0 {
+        serde_json::from_value::<PerTrackEntry>(value).map(PendingEntry::from)
+    } else {
+        // version 1 only lacks `schema_version`
+        serde_json::from_value::<PendingEntry>(value)
+    }
+    .map_err(|err| err.to_string())?;
+
+    entry.schema_version = SCHEMA_VERSION;
+    Ok((entry, version))
+}
+
+fn write_entry(path: &Path, entry: &PendingEntry) -> io::Result<()> {
+    atomic::write(path, to_json(entry)?)
+}
+
+/// Writes a new entry, failing with `AlreadyExists` if there is already one at `path`.
+fn create_entry(path: &Path, entry: &PendingEntry) -> io::Result<()> {
+    let contents = to_json(entry)?;
+    let tmp_path = atomic::write_temp(path, &contents)?;
+
+    // unlike renaming, linking won't replace an existing file
+    let res = fs::hard_link(&tmp_path, path);
+    let _ = fs::remove_file(&tmp_path);
+
+    match res {
+        Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+            // some filesystems (FAT, some network mounts) don't support hard links,
+            // so fall back to replacing the file, which races with other writers
+            debug!(
+                "Failed to link '{}', replacing it instead: {err}",
+                path.display()
+            );
+
+            if path.exists() {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+
+            atomic::write(path, contents)
+        }
+        res => res,
+    }
+}
+
+fn to_json(entry: &PendingEntry) -> io::Result<String> {
+    serde_json::to_string_pretty(entry).map_err(io::Error::other)
+}
+
+/// Rewrites entries written by older versions, in the queue and its resolved directory,
+/// in the current format.
+///
+/// Entries from when each track had its own file are merged into their release's entry.
+pub fn migrate(base_dir: &Path) {
+    for dir in [base_dir.to_path_buf(), resolved_dir(base_dir)] {
+        let files = match json_files(&dir) {
+            Ok(files) => files,
+            Err(err) => {
+                warn!("Failed to read pending queue '{}': {err}", dir.display());
+                continue;
+            }
+        };
+
+        let mut migrated = 0;
+        for (key, path) in files {
+            match migrate_entry(&dir, &key, &path) {
+                Ok(true) => migrated += 1,
+                Ok(false) => {}
+                Err(err) => warn!(
+                    "Failed to migrate pending entry '{}': {err}",
+                    path.display()
+                ),
+            }
+        }
+
+        if migrated > 0 {
+            info!("Migrated {migrated} pending entries in '{}'", dir.display());
+        }
+    }
+}
+
+/// Migrates a single entry, returning `true` if it was outdated.
+fn migrate_entry(dir: &Path, key: &str, path: &Path) -> Result<bool, String> {
+    let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let (entry, version) = parse_entry(&contents)?;
+
+    if version == SCHEMA_VERSION {
+        return Ok(false);
+    }
+
+    // per-track entries without an MBID were keyed by artist and title
+    let new_key = if version == 0 && key.starts_with("nombid_") {
+        release_key(
+            None,
+            &entry.artist,
+            &entry.album,
+            Some(entry.date.as_str()).filter(|date| !date.is_empty()),
+        )
+    } else {
+        key.to_string()
+    };
+
+    if new_key == key {
+        write_entry(path, &entry).map_err(|err| err.to_string())?;
+        return Ok(true);
+    }
+
+    debug!("Merging pending entry '{key}' into '{new_key}'");
+
+    let new_path = dir.join(format!("{new_key}.json"));
+    let merged = match read_entry(&new_path).map_err(|err| err.to_string())? {
+        Some(mut existing) => {
+            existing.added_at = existing.added_at.min(entry.added_at);
+            for track in entry.tracks {
+                insert_track(&mut existing, track);
+            }
+            existing
+        }
+        None => entry,
+    };
+
+    write_entry(&new_path, &merged).map_err(|err| err.to_string())?;
+
+    if let Some(cover) = extract::find_extracted_cover(dir, key) {
+        match cover.extension() {
+            Some(ext) if extract::find_extracted_cover(dir, &new_key).is_none() => {
+                let new_cover = dir.join(format!("{new_key}.{}", ext.to_string_lossy()));
+                fs::rename(&cover, new_cover)
+            }
+            _ => fs::remove_file(&cover),
+        }
+        .map_err(|err| err.to_string())?;
+    }
+
+    fs::remove_file(path).map_err(|err| err.to_string())?;
+    Ok(true)
+}
+
+/// Gets the key and path of each entry file in a queue directory.
+fn json_files(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut files = Vec::new();
+
+    for file in read_dir {
+        let path = file?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        if let Some(key) = path.file_stem().and_then(|key| key.to_str()) {
+            files.push((key.to_string(), path));
+        }
+    }
+
+    Ok(files)
+}
+
+/// Gets the directory resolved entries are moved to.
+pub fn resolved_dir(base_dir: &Path) -> PathBuf {
+    base_dir.join(RESOLVED_DIR)
+}
+
+/// Reads every entry in a queue directory, sorted by key.
+pub fn list_entries(dir: &Path) -> io::Result<Vec<QueuedEntry>> {
+    let mut entries = Vec::new();
+
+    for (key, path) in json_files(dir)? {
+        match get_entry(dir, &key) {
+            Ok(Some(entry)) => entries.push(entry),
+            Ok(None) => {}
+            Err(err) => warn!("Failed to read pending entry '{}': {err}", path.display()),
+        }
+    }
+
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(entries)
+}
+
+/// Reads a single entry from a queue directory.
+pub fn get_entry(dir: &Path, key: &str) -> io::Result<Option<QueuedEntry>> {
+    let entry = read_entry(&dir.join(format!("{key}.json")))?;
+
+    Ok(entry.map(|entry| QueuedEntry {
+        key: key.to_string(),
+        entry,
+        cover: extract::find_extracted_cover(dir, key),
+    }))
+}
+
+/// Marks an entry as resolved, recording the MBID and/or cover URL it was resolved with,
+/// and moves it and its cover into the resolved directory.
+///
+/// Returns the resolved entry, or `None` if there is no entry with that key.
+pub fn resolve(
+    base_dir: &Path,
+    key: &str,
+    mbid: Option<&str>,
+    url: Option<&str>,
+) -> io::Result<Option<QueuedEntry>> {
+    let Some(QueuedEntry {
+        mut entry, cover, ..
+    }) = get_entry(base_dir, key)?
+    else {
+        return Ok(None);
+    };
+
+    entry.resolved = Some(Resolution {
+        mbid: mbid.map(ToString::to_string),
+        url: url.map(ToString::to_string),
+        resolved_at: Utc::now(),
+    });
+
+    let resolved_dir = resolved_dir(base_dir);
+    fs::create_dir_all(&resolved_dir)?;
+
+    write_entry(&resolved_dir.join(format!("{key}.json")), &entry)?;
+
+    let cover = match cover {
+        Some(cover) => {
+            let file_name = cover.file_name().expect("cover to have a file name");
+            let resolved_cover = resolved_dir.join(file_name);
+            fs::rename(&cover, &resolved_cover)?;
+            Some(resolved_cover)
+        }
+        None => None,
+    };
+
+    fs::remove_file(base_dir.join(format!("{key}.json")))?;
+
+    Ok(Some(QueuedEntry {
+        key: key.to_string(),
+        entry,
+        cover,
+    }))
+}
+
+/// Deletes an entry and its cover from a queue directory.
+pub fn remove(dir: &Path, key: &str) -> io::Result<()> {
+    if let Some(cover) = extract::find_extracted_cover(dir, key) {
+        fs::remove_file(cover)?;
+    }
+
+    fs::remove_file(dir.join(format!("{key}.json")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn per_track_entry(title: &str, trackno: &str, added_at: &str) -> Value {
+        json!({
+            "reason": "no_mb_match",
+            "mbid": null,
+            "artist": "Some Artist",
+            "album": "Some Album",
+            "title": title,
+            "trackno": trackno,
+            "date": "2020",
+            "duration_secs": 180,
+            "source_path": format!("/music/{title}.flac"),
+            "added_at": added_at,
+        })
+    }
+
+    fn track(title: &str, disc: &str, trackno: &str) -> PendingTrack {
+        PendingTrack {
+            title: title.to_string(),
+            artist: "Some Artist".to_string(),
+            disc: disc.to_string(),
+            trackno: trackno.to_string(),
+            duration_secs: 180,
+            source_path: format!("/music/{title}.flac"),
+        }
+    }
+
+    fn write_json(path: &Path, value: &Value) {
+        fs::write(path, value.to_string()).expect("file to be written");
+    }
+
+    #[test]
+    fn merges_per_track_entries_into_release() {
+        let dir = tempfile::tempdir().expect("temp dir to be created");
+        let base_dir = dir.path();
+
+        write_json(
+            &base_dir.join("nombid_Some_Artist_Second_Song.json"),
+            &per_track_entry("Second Song", "2", "2024-01-02T00:00:00Z"),
+        );
+        write_json(
+            &base_dir.join("nombid_Some_Artist_First_Song.json"),
+            &per_track_entry("First Song", "1", "2024-01-03T00:00:00Z"),
+        );
+        fs::write(
+            base_dir.join("nombid_Some_Artist_Second_Song.png"),
+            b"cover",
+        )
+        .expect("cover to be written");
+
+        migrate(base_dir);
+
+        let entries = list_entries(base_dir).expect("queue to be read");
+        assert_eq!(entries.len(), 1);
+
+        let QueuedEntry { key, entry, cover } = &entries[0];
+        assert_eq!(key, "nombid_Some_Artist_Some_Album_2020");
+        assert_eq!(entry.schema_version, SCHEMA_VERSION);
+        assert_eq!(entry.added_at.to_rfc3339(), "2024-01-02T00:00:00+00:00");
+
+        let titles = entry
+            .tracks
+            .iter()
+            .map(|track| track.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["First Song", "Second Song"]);
+
+        let cover = cover.as_ref().expect("cover to be moved");
+        assert_eq!(
+            cover,
+            &base_dir.join("nombid_Some_Artist_Some_Album_2020.png")
+        );
+        assert_eq!(fs::read(cover).expect("cover to be read"), b"cover");
+        assert!(!base_dir.join("nombid_Some_Artist_Second_Song.png").exists());
+
+        let on_disk: Value = serde_json::from_str(
+            &fs::read_to_string(base_dir.join(format!("{key}.json"))).expect("entry to be read"),
+        )
+        .expect("entry to be JSON");
+        assert_eq!(on_disk["schema_version"], SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn refuses_newer_schema_version() {
+        let dir = tempfile::tempdir().expect("temp dir to be created");
+        let path = dir.path().join("some-mbid.json");
+
+        let mut value = per_track_entry("Song", "1", "2024-01-01T00:00:00Z");
+        value["schema_version"] = json!(SCHEMA_VERSION + 1);
+        value["tracks"] = json!([]);
+        value["future_field"] = json!("kept");
+        write_json(&path, &value);
+
+        let contents = fs::read_to_string(&path).expect("entry to be read");
+        assert!(parse_entry(&contents).is_err());
+
+        migrate(dir.path());
+
+        // left untouched rather than rewritten without the fields it doesn't know
+        assert_eq!(
+            fs::read_to_string(&path).expect("entry to be read"),
+            contents
+        );
+        assert!(get_entry(dir.path(), "some-mbid").is_err());
+    }
+
+    #[test]
+    fn groups_tracks_by_release_in_disc_and_track_order() {
+        let dir = tempfile::tempdir().expect("temp dir to be created");
+
+        let song_tags = |title: &str, artist: &str| {
+            [
+                (Tag::Title, title),
+                (Tag::Artist, artist),
+                (Tag::AlbumArtist, "Some Artist"),
+                (Tag::Album, "Some Album"),
+                (Tag::Date, "2020"),
+            ]
+            .into_iter()
+            .map(|(tag, value)| (tag, vec![value.to_string()]))
+            .collect::<HashMap<_, _>>()
+        };
+        let missing = MissingRelease {
+            reason: "no_mb_match",
+            mbid: None,
+            release_group_id: None,
+            lookup: None,
+        };
+
+        let tracks = [
+            (
+                song_tags("Second Disc", "Some Artist"),
+                track("Second Disc", "2", "1"),
+            ),
+            (song_tags("Tenth", "Guest"), track("Tenth", "1", "10")),
+            (
+                song_tags("Third", "Some Artist"),
+                track("Third", "1", "3/12"),
+            ),
+            // played again
+            (song_tags("Tenth", "Guest"), track("Tenth", "1", "10")),
+        ];
+
+        for (tags, track) in tracks {
+            let key = tags_key(&tags);
+            assert_eq!(key, "nombid_Some_Artist_Some_Album_2020");
+
+            add_to_entry(
+                &dir.path().join(format!("{key}.json")),
+                new_entry(&tags, &missing),
+                track,
+            )
+            .expect("track to be added");
+        }
+
+        let entries = list_entries(dir.path()).expect("queue to be read");
+        assert_eq!(entries.len(), 1);
+
+        let titles = entries[0]
+            .entry
+            .tracks
+            .iter()
+            .map(|track| track.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["Third", "Tenth", "Second Disc"]);
+    }
+}
@@ -0,0 +1,62 @@
+use super::http;
+use super::musicbrainz::{LookupSource, Type};
+use mpd_client::responses::Song;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The result of asking a single provider for an album's cover.
+#[derive(Debug)]
+pub enum ArtLookup {
+    /// A public URL to the cover image.
+    Found {
+        url: String,
+        /// MusicBrainz record the cover is from, if found through Cover Art Archive.
+        record: Option<(String, Type)>,
+    },
+    /// A cover image on this machine,
+    /// which needs uploading before it can be displayed.
+    Local(PathBuf),
+    /// The provider has no cover for the album.
+    /// Set if the album should be added to the pending MusicBrainz queue.
+    NotFound(Option<MissingRelease>),
+}
+
+/// Details of an album missing from MusicBrainz or Cover Art Archive,
+/// written to the pending queue.
+#[derive(Debug, Clone)]
+pub struct MissingRelease {
+    /// - `missing_caa`: MusicBrainz has the release, but Cover Art Archive has no art
+    /// - `no_mb_match`: MusicBrainz couldn't find the release
+    pub reason: &'static str,
+    /// Release ID the song is tagged with.
+    pub mbid: Option<String>,
+    /// Release group the cover was looked for on, if it was found.
+    pub release_group_id: Option<String>,
+    /// How the MusicBrainz record was found, if it was.
+    pub lookup: Option<LookupSource>,
+}
+
+/// A source of album covers.
+///
+/// Providers are asked in the order configured in `album_art.providers`,
+/// stopping at the first which finds a cover.
+pub trait ArtProvider: Send + Sync {
+    /// Name used in logs and the cache.
+    fn name(&self) -> &'static str;
+
+    /// Looks up the cover for the album `song` is on.
+    ///
+    /// `music_dir` is the directory the song's URL is relative to.
+    /// `artist` and `album` are the album artist (or artist) and album tags.
+    /// Errors are treated as temporary, so the result is not cached.
+    fn find_art<'a>(
+        &'a self,
+        song: &'a Song,
+        music_dir: &'a Path,
+        artist: &'a str,
+        album: &'a str,
+    ) -> BoxFuture<'a, http::Result<ArtLookup>>;
+}
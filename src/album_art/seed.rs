@@ -0,0 +1,265 @@
+//! Pages for adding pending releases to MusicBrainz,
+//! using the release editor's seeding:
+//! https://musicbrainz.org/doc/Development/Release_Editor_Seeding
+
+use super::pending::{PendingEntry, PendingTrack, QueuedEntry, track_number};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const MUSICBRAINZ_URL: &str = "https://musicbrainz.org";
+
+/// Renders an HTML page with a form that opens the MusicBrainz release editor
+/// filled in with a queued release's details and tracklist.
+///
+/// Refuses releases which are already on MusicBrainz and only missing a cover,
+/// as adding them again would create a duplicate.
+pub fn render(QueuedEntry { key, entry, cover }: &QueuedEntry) -> Result<String, String> {
+    let PendingEntry {
+        reason,
+        mbid,
+        release_group_id,
+        artist,
+        album,
+        date,
+        tracks,
+        ..
+    } = entry;
+
+    if let Some(mbid) = mbid {
+        return Err(format!(
+            "release {mbid} is already on MusicBrainz, add its cover at {MUSICBRAINZ_URL}/release/{mbid}/add-cover-art"
+        ));
+    }
+
+    if reason == "missing_caa" {
+        return Err(match release_group_id {
+            Some(id) => format!(
+                "the release is already on MusicBrainz, add its cover from {MUSICBRAINZ_URL}/release-group/{id}"
+            ),
+            None => "the release is already on MusicBrainz".to_string(),
+        });
+    }
+
+    let mut fields = vec![
+        ("name".to_string(), album.clone()),
+        ("artist_credit.names.0.name".to_string(), artist.clone()),
+    ];
+
+    for (part, value) in ["year", "month", "day"].iter().zip(date.split('-')) {
+        if let Ok(value) = value.trim().parse::<u32>() {
+            fields.push((format!("events.0.date.{part}"), value.to_string()));
+        }
+    }
+
+    // MusicBrainz numbers mediums from 0, in order
+    let mut discs = BTreeMap::<u32, Vec<&PendingTrack>>::new();
+    for track in tracks {
+        discs
+            .entry(track_number(&track.disc))
+            .or_default()
+            .push(track);
+    }
+
+    for (medium, tracks) in discs.values().enumerate() {
+        for (i, track) in tracks.iter().enumerate() {
+            let prefix = format!("mediums.{medium}.track.{i}");
+
+            fields.push((format!("{prefix}.name"), track.title.clone()));
+
+            let number = track_number(&track.trackno);
+            if number > 0 {
+                fields.push((format!("{prefix}.number"), number.to_string()));
+            }
+
+            if track.duration_secs > 0 {
+                fields.push((
+                    format!("{prefix}.length"),
+                    (track.duration_secs * 1000).to_string(),
+                ));
+            }
+
+            if !track.artist.is_empty() && track.artist != *artist {
+                fields.push((
+                    format!("{prefix}.artist_credit.names.0.name"),
+                    track.artist.clone(),
+                ));
+            }
+        }
+    }
+
+    fields.push((
+        "edit_note".to_string(),
+        format!(
+            "Tracklist and track lengths from the tags of local files, seeded by {} ({}). Only tracks which have been played are included.",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_REPOSITORY")
+        ),
+    ));
+
+    let mut html = String::new();
+
+    // writing to a string can't fail
+    let _ = writeln!(
+        html,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Add {title} to MusicBrainz</title>
+</head>
+<body>
+<h1>{title}</h1>
+<p>Queued as <code>{key}</code>. Check the tracklist is complete in the release editor before submitting.</p>"#,
+        title = escape(&format!("{artist} - {album}")),
+        key = escape(key),
+    );
+
+    if let Some(cover) = cover {
+        let _ = writeln!(
+            html,
+            r#"<p>Once added, upload the cover from <a href="file://{path}">{path}</a>.</p>"#,
+            path = escape(&cover.to_string_lossy()),
+        );
+    }
+
+    let _ = writeln!(
+        html,
+        r#"<form action="{MUSICBRAINZ_URL}/release/add" method="post" accept-charset="utf-8">"#
+    );
+
+    for (name, value) in &fields {
+        let _ = writeln!(
+            html,
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            escape(name),
+            escape(value)
+        );
+    }
+
+    let _ = writeln!(
+        html,
+        r#"<button type="submit">Add release to MusicBrainz</button>
+</form>
+</body>
+</html>"#
+    );
+
+    Ok(html)
+}
+
+/// Escapes text for use in HTML content and attribute values.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn track(disc: &str, trackno: &str, title: &str, artist: &str) -> PendingTrack {
+        PendingTrack {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            disc: disc.to_string(),
+            trackno: trackno.to_string(),
+            duration_secs: 185,
+            source_path: format!("/music/{title}.flac"),
+        }
+    }
+
+    fn queued(reason: &str, mbid: Option<&str>) -> QueuedEntry {
+        QueuedEntry {
+            key: "nombid_Tom_Jerry_Live".to_string(),
+            entry: PendingEntry {
+                schema_version: 2,
+                reason: reason.to_string(),
+                mbid: mbid.map(ToString::to_string),
+                release_group_id: None,
+                lookup: None,
+                artist: "Tom & Jerry".to_string(),
+                album: r#"Live at "The <Club>""#.to_string(),
+                date: "2019-04-07".to_string(),
+                added_at: Utc::now(),
+                tracks: vec![
+                    track("1", "2/12", "Second", "Tom & Jerry"),
+                    track("1", "1/12", "First", "Tom & Jerry"),
+                    track("2", "1", "Encore", "Tom's Guest"),
+                ],
+                resolved: None,
+            },
+            cover: None,
+        }
+    }
+
+    fn input(name: &str, value: &str) -> String {
+        format!(r#"<input type="hidden" name="{name}" value="{value}">"#)
+    }
+
+    #[test]
+    fn seeds_release_editor_fields() {
+        let mut entry = queued("no_mb_match", None);
+        entry
+            .entry
+            .tracks
+            .sort_by_key(|track| (track_number(&track.disc), track_number(&track.trackno)));
+
+        let html = render(&entry).expect("entry to be seeded");
+
+        for expected in [
+            input("name", "Live at &quot;The &lt;Club&gt;&quot;"),
+            input("artist_credit.names.0.name", "Tom &amp; Jerry"),
+            input("events.0.date.year", "2019"),
+            input("events.0.date.month", "4"),
+            input("events.0.date.day", "7"),
+            input("mediums.0.track.0.name", "First"),
+            input("mediums.0.track.0.number", "1"),
+            input("mediums.0.track.0.length", "185000"),
+            input("mediums.0.track.1.name", "Second"),
+            input("mediums.0.track.1.number", "2"),
+            input("mediums.1.track.0.name", "Encore"),
+            input(
+                "mediums.1.track.0.artist_credit.names.0.name",
+                "Tom&#39;s Guest",
+            ),
+        ] {
+            assert!(html.contains(&expected), "missing {expected} in {html}");
+        }
+
+        // track artists matching the release's are left to the release's credit
+        assert!(!html.contains("mediums.0.track.0.artist_credit"));
+        assert!(html.contains(r#"action="https://musicbrainz.org/release/add""#));
+        assert!(html.contains("<title>Add Tom &amp; Jerry - Live at &quot;The &lt;Club&gt;&quot; to MusicBrainz</title>"));
+    }
+
+    #[test]
+    fn refuses_releases_already_on_musicbrainz() {
+        let err = render(&queued("missing_caa", Some("some-mbid"))).expect_err("to be refused");
+        assert!(err.contains("https://musicbrainz.org/release/some-mbid/add-cover-art"));
+
+        let mut entry = queued("missing_caa", None);
+        entry.entry.release_group_id = Some("some-group".to_string());
+        let err = render(&entry).expect_err("to be refused");
+        assert!(err.contains("https://musicbrainz.org/release-group/some-group"));
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("plain"), "plain");
+    }
+}
@@ -0,0 +1,144 @@
+//! A minimal HTTP server on localhost for testing against canned responses.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A request received by the server.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path including the query string.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response to send, from a handler.
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl From<(u16, String)> for Response {
+    fn from((status, body): (u16, String)) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+}
+
+pub struct TestServer {
+    /// Base URL of the server, without a trailing slash.
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    /// Starts a server responding to each request
+    /// with the response (or status and body) returned by `handler`.
+    pub fn start<F, R>(handler: F) -> Self
+    where
+        F: Fn(&Request) -> R + Send + 'static,
+        R: Into<Response>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("test server to bind");
+        let url = format!("http://{}", listener.local_addr().expect("bound address"));
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else {
+                        continue;
+                    };
+
+                    let Some(request) = read_request(&mut stream) else {
+                        continue;
+                    };
+
+                    let Response {
+                        status,
+                        headers,
+                        body,
+                    } = handler(&request).into();
+                    requests.lock().expect("lock not poisoned").push(request);
+
+                    let headers = headers
+                        .iter()
+                        .map(|(key, value)| format!("{key}: {value}\r\n"))
+                        .collect::<String>();
+
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status} Test\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{headers}\r\n{body}",
+                        body.len()
+                    );
+                }
+            });
+        }
+
+        Self { url, requests }
+    }
+
+    /// Gets the requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().expect("lock not poisoned").clone()
+    }
+}
+
+fn read_request(stream: &mut impl Read) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        let (key, value) = line.split_once(':')?;
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+
+    let length = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).ok()?;
+
+    Some(request)
+}
@@ -0,0 +1,340 @@
+use crate::album_art::pending::{self, QueuedEntry};
+use crate::album_art::seed;
+use crate::config::Config;
+use chrono::{TimeDelta, Utc};
+use clap::{Args, Parser, Subcommand};
+use serde_json::{Value, json};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Manage the queue of releases missing from MusicBrainz / Cover Art Archive.
+    Queue(QueueArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct QueueArgs {
+    /// Print JSON instead of a table, for scripting.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: QueueCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum QueueCommand {
+    /// List queued releases.
+    List {
+        /// List resolved releases instead.
+        #[arg(long)]
+        resolved: bool,
+    },
+    /// Show a queued release and its tracks.
+    Show { key: String },
+    /// Mark a release as resolved, moving it out of the queue.
+    Resolve {
+        key: String,
+        /// MusicBrainz release ID the release was added as.
+        #[arg(long)]
+        mbid: Option<String>,
+        /// URL of the release's cover.
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Write a page which opens the MusicBrainz release editor
+    /// filled in with a queued release's details and tracklist.
+    Seed {
+        key: String,
+        /// Where to write the page.
+        /// Defaults to a file in the system's temporary directory.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Delete queued releases added before a given age.
+    Prune {
+        /// Age, eg `30d`, `12h`, `2w`.
+        #[arg(long, value_parser = parse_age)]
+        older_than: TimeDelta,
+        /// Prune resolved releases (by when they were resolved) instead.
+        #[arg(long)]
+        resolved: bool,
+    },
+}
+
+/// Runs a queue subcommand, returning an error message on failure.
+pub fn run_queue(args: QueueArgs, config: &Config) -> Result<(), String> {
+    let base_dir = &config.pending_queue.dir;
+    pending::migrate(base_dir);
+
+    match args.command {
+        QueueCommand::List { resolved } => {
+            let dir = if resolved {
+                pending::resolved_dir(base_dir)
+            } else {
+                base_dir.clone()
+            };
+
+            let entries = pending::list_entries(&dir)
+                .map_err(|err| format!("failed to read '{}': {err}", dir.display()))?;
+
+            if args.json {
+                print_json(&Value::Array(entries.iter().map(entry_json).collect()));
+            } else {
+                print_list(&entries);
+            }
+        }
+        QueueCommand::Show { key } => {
+            let entry = get_entry(base_dir, &key)?;
+
+            if args.json {
+                print_json(&entry_json(&entry));
+            } else {
+                print_entry(&entry);
+            }
+        }
+        QueueCommand::Resolve { key, mbid, url } => {
+            let entry = pending::resolve(base_dir, &key, mbid.as_deref(), url.as_deref())
+                .map_err(|err| format!("failed to resolve '{key}': {err}"))?
+                .ok_or_else(|| format!("no queued release with key '{key}'"))?;
+
+            if args.json {
+                print_json(&entry_json(&entry));
+            } else {
+                println!("Resolved '{key}'");
+            }
+        }
+        QueueCommand::Seed { key, output } => {
+            let entry = get_entry(base_dir, &key)?;
+            let path = output.unwrap_or_else(|| {
+                std::env::temp_dir().join(format!("{}-seed-{key}.html", env!("CARGO_PKG_NAME")))
+            });
+
+            let html = seed::render(&entry).map_err(|err| format!("can't seed '{key}': {err}"))?;
+
+            fs::write(&path, html)
+                .map_err(|err| format!("failed to write '{}': {err}", path.display()))?;
+
+            if args.json {
+                print_json(&json!({ "path": path }));
+            } else {
+                println!(
+                    "Wrote '{}', open it in a browser to add the release",
+                    path.display()
+                );
+            }
+        }
+        QueueCommand::Prune {
+            older_than,
+            resolved,
+        } => {
+            let dir = if resolved {
+                pending::resolved_dir(base_dir)
+            } else {
+                base_dir.clone()
+            };
+
+            let cutoff = Utc::now() - older_than;
+
+            let entries = pending::list_entries(&dir)
+                .map_err(|err| format!("failed to read '{}': {err}", dir.display()))?;
+
+            let mut pruned = Vec::new();
+            for entry in entries {
+                let time = if resolved {
+                    // resolved entries without a resolution are kept
+                    let Some(resolution) = &entry.entry.resolved else {
+                        continue;
+                    };
+                    resolution.resolved_at
+                } else {
+                    entry.entry.added_at
+                };
+
+                if time < cutoff {
+                    pending::remove(&dir, &entry.key)
+                        .map_err(|err| format!("failed to remove '{}': {err}", entry.key))?;
+                    pruned.push(entry.key);
+                }
+            }
+
+            if args.json {
+                print_json(&json!(pruned));
+            } else {
+                for key in &pruned {
+                    println!("Removed '{key}'");
+                }
+                println!("Pruned {} releases", pruned.len());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get_entry(base_dir: &Path, key: &str) -> Result<QueuedEntry, String> {
+    let entry = match pending::get_entry(base_dir, key) {
+        Ok(None) => pending::get_entry(&pending::resolved_dir(base_dir), key),
+        entry => entry,
+    };
+
+    entry
+        .map_err(|err| format!("failed to read '{key}': {err}"))?
+        .ok_or_else(|| format!("no queued release with key '{key}'"))
+}
+
+/// Parses an age such as `30d` into a duration.
+/// Supports `s`, `m`, `h`, `d` and `w` units.
+fn parse_age(value: &str) -> Result<TimeDelta, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in '{value}', eg `30d`"))?;
+    let (amount, unit) = value.split_at(split);
+
+    let amount = amount
+        .parse::<i64>()
+        .map_err(|_| format!("invalid number in '{value}'"))?;
+
+    let delta = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        _ => return Err(format!("unknown unit '{unit}', expected s, m, h, d or w")),
+    };
+
+    delta.ok_or_else(|| format!("'{value}' is too large"))
+}
+
+/// Gets the entry as JSON, with its key and cover path included.
+fn entry_json(entry: &QueuedEntry) -> Value {
+    let mut json = serde_json::to_value(&entry.entry).expect("entry to serialize");
+
+    if let Some(object) = json.as_object_mut() {
+        object.insert("key".to_string(), json!(entry.key));
+        object.insert("cover".to_string(), json!(entry.cover));
+    }
+
+    json
+}
+
+fn print_json(value: &Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("JSON value to serialize")
+    );
+}
+
+fn print_list(entries: &[QueuedEntry]) {
+    let rows = entries
+        .iter()
+        .map(|QueuedEntry { key, entry, cover }| {
+            vec![
+                key.clone(),
+                entry.reason.clone(),
+                entry.artist.clone(),
+                entry.album.clone(),
+                entry.date.clone(),
+                entry.tracks.len().to_string(),
+                if cover.is_some() { "yes" } else { "no" }.to_string(),
+                entry.added_at.to_rfc3339(),
+            ]
+        })
+        .collect();
+
+    print_table(
+        &[
+            "KEY", "REASON", "ARTIST", "ALBUM", "DATE", "TRACKS", "COVER", "ADDED",
+        ],
+        rows,
+    );
+}
+
+fn print_entry(QueuedEntry { key, entry, cover }: &QueuedEntry) {
+    println!("Key:     {key}");
+    println!("Reason:  {}", entry.reason);
+    println!("MBID:    {}", entry.mbid.as_deref().unwrap_or_default());
+    println!(
+        "Release group: {}",
+        entry.release_group_id.as_deref().unwrap_or_default()
+    );
+    println!("Artist:  {}", entry.artist);
+    println!("Album:   {}", entry.album);
+    println!("Date:    {}", entry.date);
+    println!(
+        "Cover:   {}",
+        cover
+            .as_ref()
+            .map_or("none".to_string(), |cover| cover.display().to_string())
+    );
+    println!("Added:   {}", entry.added_at.to_rfc3339());
+
+    if let Some(resolved) = &entry.resolved {
+        println!(
+            "Resolved: {} (MBID: {}, URL: {})",
+            resolved.resolved_at.to_rfc3339(),
+            resolved.mbid.as_deref().unwrap_or_default(),
+            resolved.url.as_deref().unwrap_or_default(),
+        );
+    }
+
+    println!();
+
+    let rows = entry
+        .tracks
+        .iter()
+        .map(|track| {
+            vec![
+                track.disc.clone(),
+                track.trackno.clone(),
+                track.title.clone(),
+                track.artist.clone(),
+                format!(
+                    "{}:{:02}",
+                    track.duration_secs / 60,
+                    track.duration_secs % 60
+                ),
+                track.source_path.clone(),
+            ]
+        })
+        .collect();
+
+    print_table(&["DISC", "#", "TITLE", "ARTIST", "LENGTH", "PATH"], rows);
+}
+
+/// Prints rows as columns aligned to the widest value in each.
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect::<Vec<_>>();
+
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let print_row = |values: &mut dyn Iterator<Item = &str>| {
+        let line = values
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&mut headers.iter().copied());
+    for row in &rows {
+        print_row(&mut row.iter().map(String::as_str));
+    }
+}
@@ -1,36 +1,29 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
 use std::default::Default;
+use std::env;
+use std::path::{Path, PathBuf};
 use universal_config::ConfigLoader;
 
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum TimestampMode {
     Elapsed,
     Left,
     Off,
+    #[default]
     Both,
 }
 
-impl Default for TimestampMode {
-    fn default() -> Self {
-        Self::Both
-    }
-}
-
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum DisplayType {
     Name,
+    #[default]
     State,
     Details,
 }
 
-impl Default for DisplayType {
-    fn default() -> Self {
-        Self::State
-    }
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Format {
     #[serde(default = "default_details_format")]
@@ -66,6 +59,319 @@ impl Default for Format {
     }
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtProviderKind {
+    /// MusicBrainz / Cover Art Archive
+    Musicbrainz,
+    /// iTunes Search API
+    Itunes,
+    /// Deezer search API
+    Deezer,
+    /// Last.fm `album.getInfo`
+    Lastfm,
+    /// Cover image file next to the song, eg `cover.jpg`.
+    /// Needs an uploader for the cover to be displayed.
+    Local,
+}
+
+/// Size of the Cover Art Archive image to use.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CoverArtSize {
+    #[default]
+    #[serde(rename = "250")]
+    Small,
+    #[serde(rename = "500")]
+    Large,
+    #[serde(rename = "1200")]
+    ExtraLarge,
+    /// The image as uploaded, which may be very large.
+    #[serde(rename = "original")]
+    Original,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MusicbrainzConfig {
+    /// Root of the MusicBrainz web service, eg for a local mirror.
+    #[serde(default = "default_musicbrainz_base_url")]
+    pub base_url: String,
+    #[serde(default = "default_cover_art_base_url")]
+    pub cover_art_base_url: String,
+    /// Contact details (email or URL) sent in the user agent,
+    /// which MusicBrainz asks of API clients.
+    /// Defaults to this project's repository.
+    #[serde(default)]
+    pub contact: Option<String>,
+    #[serde(default)]
+    pub cover_size: CoverArtSize,
+    /// If the release has no front cover,
+    /// use another of its images instead (preferring the back cover).
+    #[serde(default)]
+    pub fallback_images: bool,
+}
+
+impl Default for MusicbrainzConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_musicbrainz_base_url(),
+            cover_art_base_url: default_cover_art_base_url(),
+            contact: None,
+            cover_size: CoverArtSize::default(),
+            fallback_images: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ItunesConfig {
+    #[serde(default = "default_itunes_base_url")]
+    pub base_url: String,
+    /// Width and height in pixels to request covers at.
+    #[serde(default = "default_itunes_artwork_size")]
+    pub artwork_size: u32,
+    /// Two-letter country code of the store to search.
+    /// Uses the US store if not set.
+    #[serde(default)]
+    pub country: Option<String>,
+}
+
+impl Default for ItunesConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_itunes_base_url(),
+            artwork_size: default_itunes_artwork_size(),
+            country: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeezerCoverSize {
+    /// 56x56
+    Small,
+    /// 250x250
+    Medium,
+    /// 500x500
+    Big,
+    /// 1000x1000
+    #[default]
+    Xl,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeezerConfig {
+    #[serde(default = "default_deezer_base_url")]
+    pub base_url: String,
+    #[serde(default)]
+    pub cover_size: DeezerCoverSize,
+}
+
+impl Default for DeezerConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_deezer_base_url(),
+            cover_size: DeezerCoverSize::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LastfmConfig {
+    #[serde(default = "default_lastfm_base_url")]
+    pub base_url: String,
+    /// Falls back to the `LASTFM_API_KEY` environment variable if not set.
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+impl Default for LastfmConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_lastfm_base_url(),
+            api_key: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LocalConfig {
+    /// File names to look for in the song's directory, in order of preference.
+    /// `*` and `?` wildcards are supported, and matching ignores case.
+    #[serde(default = "default_local_patterns")]
+    pub patterns: Vec<String>,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            patterns: default_local_patterns(),
+        }
+    }
+}
+
+/// Where local covers are uploaded to, so Discord can display them.
+///
+/// Images are named by the SHA-256 hash of their contents, eg `<hash>.jpg`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UploadConfig {
+    /// `PUT`s the image to `<url>/<name>`,
+    /// after which it is available at `<public_url>/<name>`.
+    Put {
+        url: String,
+        public_url: String,
+        /// Extra headers to send, eg for authorization.
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// `POST`s the image as a multipart form to `url`,
+    /// reading the image's URL from the response.
+    Multipart {
+        url: String,
+        /// Name of the form field the image is sent in.
+        #[serde(default = "default_multipart_field")]
+        field: String,
+        /// Extra headers to send, eg for authorization.
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        /// JSON pointer (eg `/data/url`) to the URL in a JSON response.
+        /// If not set, the whole response body is taken as the URL.
+        #[serde(default)]
+        response_url_pointer: Option<String>,
+    },
+    /// Uploads the image to an S3-compatible bucket,
+    /// after which it is available at `<public_url>/<prefix><name>`.
+    S3 {
+        /// Endpoint of the S3 API. The bucket is appended as the first path segment.
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_s3_region")]
+        region: String,
+        access_key: String,
+        secret_key: String,
+        public_url: String,
+        /// Prepended to each object's key, eg `covers/`.
+        #[serde(default)]
+        prefix: String,
+        /// Canned ACL to apply to each object, eg `public-read`.
+        #[serde(default)]
+        acl: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlbumArtConfig {
+    /// Where to look for album covers, in order.
+    #[serde(default = "default_art_providers")]
+    pub providers: Vec<ArtProviderKind>,
+    /// Directory MPD's song URLs are relative to.
+    /// Only used when MPD refuses to report its own `music_directory`,
+    /// which it does for clients not connected over a local socket.
+    #[serde(default = "default_music_root", deserialize_with = "deserialize_path")]
+    pub music_root: PathBuf,
+    /// Whether to read covers embedded in the audio file when MPD can't provide one.
+    /// This requires the music files to be on this machine, under the music directory.
+    #[serde(default = "default_true", alias = "ffmpeg_fallback")]
+    pub embedded_fallback: bool,
+    /// File album art lookups are cached in.
+    #[serde(
+        default = "default_album_art_cache_file",
+        deserialize_with = "deserialize_path"
+    )]
+    pub cache_file: PathBuf,
+    /// File of manual overrides for albums' covers, reloaded when edited.
+    /// TOML, or JSON if the file name ends in `.json`.
+    #[serde(
+        default = "default_overrides_file",
+        deserialize_with = "deserialize_path"
+    )]
+    pub overrides_file: PathBuf,
+    /// How long a found cover is cached for.
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// How long a failed lookup is cached for before being retried.
+    #[serde(default = "default_negative_cache_ttl_secs")]
+    pub negative_cache_ttl_secs: u64,
+    /// How long to wait for each HTTP request before giving up on it.
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    /// How many times a failed HTTP request is retried.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Minimum score (from 0 to 1) a search result needs to be accepted as a match.
+    /// Combines the search service's relevance with how closely the artist and album names match.
+    #[serde(default = "default_min_match_score")]
+    pub min_match_score: f64,
+    #[serde(default)]
+    pub musicbrainz: MusicbrainzConfig,
+    #[serde(default)]
+    pub itunes: ItunesConfig,
+    #[serde(default)]
+    pub deezer: DeezerConfig,
+    #[serde(default)]
+    pub lastfm: LastfmConfig,
+    #[serde(default)]
+    pub local: LocalConfig,
+    /// If set, local cover files and covers extracted from songs are uploaded here,
+    /// and shown when no provider has the album.
+    #[serde(default)]
+    pub upload: Option<UploadConfig>,
+}
+
+impl Default for AlbumArtConfig {
+    fn default() -> Self {
+        Self {
+            providers: default_art_providers(),
+            music_root: default_music_root(),
+            embedded_fallback: true,
+            cache_file: default_album_art_cache_file(),
+            overrides_file: default_overrides_file(),
+            cache_ttl_secs: default_cache_ttl_secs(),
+            negative_cache_ttl_secs: default_negative_cache_ttl_secs(),
+            request_timeout_secs: default_request_timeout_secs(),
+            max_retries: default_max_retries(),
+            min_match_score: default_min_match_score(),
+            musicbrainz: MusicbrainzConfig::default(),
+            itunes: ItunesConfig::default(),
+            deezer: DeezerConfig::default(),
+            lastfm: LastfmConfig::default(),
+            local: LocalConfig::default(),
+            upload: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PendingQueueConfig {
+    /// Directory that releases missing from MusicBrainz / Cover Art Archive
+    /// are written to.
+    #[serde(
+        default = "default_pending_queue_dir",
+        deserialize_with = "deserialize_path"
+    )]
+    pub dir: PathBuf,
+    /// How often to check whether queued releases
+    /// have been added to MusicBrainz / Cover Art Archive, in seconds.
+    /// The first check is shortly after starting.
+    /// `0` disables checking.
+    #[serde(default = "default_recheck_interval_secs")]
+    pub recheck_interval_secs: u64,
+    /// How long to wait between checking each queued release, in seconds.
+    #[serde(default = "default_recheck_delay_secs")]
+    pub recheck_delay_secs: u64,
+}
+
+impl Default for PendingQueueConfig {
+    fn default() -> Self {
+        Self {
+            dir: default_pending_queue_dir(),
+            recheck_interval_secs: default_recheck_interval_secs(),
+            recheck_delay_secs: default_recheck_delay_secs(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     #[serde(default = "default_discord_id")]
@@ -74,6 +380,10 @@ pub struct Config {
     pub hosts: Vec<String>,
     #[serde(default)]
     pub format: Format,
+    #[serde(default)]
+    pub album_art: AlbumArtConfig,
+    #[serde(default)]
+    pub pending_queue: PendingQueueConfig,
 }
 
 impl Default for Config {
@@ -82,6 +392,8 @@ impl Default for Config {
             id: default_discord_id(),
             hosts: default_mpd_hosts(),
             format: Format::default(),
+            album_art: AlbumArtConfig::default(),
+            pending_queue: PendingQueueConfig::default(),
         }
     }
 }
@@ -120,3 +432,216 @@ const fn default_discord_id() -> u64 {
 fn default_mpd_hosts() -> Vec<String> {
     vec!["localhost:6600".to_string()]
 }
+
+fn default_art_providers() -> Vec<ArtProviderKind> {
+    vec![ArtProviderKind::Musicbrainz]
+}
+
+const fn default_true() -> bool {
+    true
+}
+
+/// Gets the user's music directory:
+/// `$XDG_MUSIC_DIR`, or as set in `user-dirs.dirs`, or `~/Music`.
+fn default_music_root() -> PathBuf {
+    env::var_os("XDG_MUSIC_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let contents =
+                std::fs::read_to_string(xdg_config_home().join("user-dirs.dirs")).ok()?;
+            parse_user_dir(&contents, "XDG_MUSIC_DIR", &home_dir())
+        })
+        .unwrap_or_else(|| home_dir().join("Music"))
+}
+
+/// Reads a directory from the contents of `user-dirs.dirs`,
+/// which holds lines like `XDG_MUSIC_DIR="$HOME/Music"`.
+///
+/// Values are either relative to `$HOME` or absolute.
+fn parse_user_dir(contents: &str, name: &str, home: &Path) -> Option<PathBuf> {
+    let value = contents
+        .lines()
+        .filter_map(|line| line.trim().split_once('='))
+        .find(|(key, _)| key.trim() == name)?
+        .1
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')?;
+
+    if value == "$HOME" {
+        // xdg-user-dirs sets disabled directories to the home directory
+        None
+    } else if let Some(relative) = value.strip_prefix("$HOME/") {
+        Some(home.join(relative))
+    } else if value.starts_with('/') {
+        Some(PathBuf::from(value))
+    } else {
+        None
+    }
+}
+
+fn default_album_art_cache_file() -> PathBuf {
+    data_dir().join("album_art_cache.json")
+}
+
+fn default_overrides_file() -> PathBuf {
+    config_dir().join("album_art_overrides.toml")
+}
+
+const fn default_cache_ttl_secs() -> u64 {
+    // 30 days
+    30 * 24 * 60 * 60
+}
+
+const fn default_negative_cache_ttl_secs() -> u64 {
+    // 1 day
+    24 * 60 * 60
+}
+
+const fn default_request_timeout_secs() -> u64 {
+    10
+}
+
+const fn default_max_retries() -> u32 {
+    3
+}
+
+const fn default_min_match_score() -> f64 {
+    0.75
+}
+
+fn default_itunes_base_url() -> String {
+    "https://itunes.apple.com".to_string()
+}
+
+const fn default_itunes_artwork_size() -> u32 {
+    600
+}
+
+fn default_deezer_base_url() -> String {
+    "https://api.deezer.com".to_string()
+}
+
+fn default_musicbrainz_base_url() -> String {
+    "https://musicbrainz.org/ws/2".to_string()
+}
+
+fn default_cover_art_base_url() -> String {
+    "https://coverartarchive.org".to_string()
+}
+
+fn default_lastfm_base_url() -> String {
+    "https://ws.audioscrobbler.com/2.0".to_string()
+}
+
+fn default_local_patterns() -> Vec<String> {
+    ["cover.*", "folder.*", "front.*"]
+        .into_iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn default_multipart_field() -> String {
+    "file".to_string()
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_pending_queue_dir() -> PathBuf {
+    data_dir().join("pending_musicbrainz")
+}
+
+const fn default_recheck_interval_secs() -> u64 {
+    // 6 hours
+    6 * 60 * 60
+}
+
+const fn default_recheck_delay_secs() -> u64 {
+    10
+}
+
+fn home_dir() -> PathBuf {
+    env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/"))
+}
+
+/// Gets the directory the config file is stored in,
+/// following the XDG base directory spec.
+pub fn config_dir() -> PathBuf {
+    xdg_config_home().join("discord-rpc")
+}
+
+fn xdg_config_home() -> PathBuf {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir().join(".config"))
+}
+
+/// Gets the directory persistent application data is stored in,
+/// following the XDG base directory spec.
+pub fn data_dir() -> PathBuf {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir().join(".local").join("share"))
+        .join("mpd-rpc")
+}
+
+/// Expands a leading `~` in a path to the user's home directory.
+pub fn expand_path(path: &str) -> PathBuf {
+    if path == "~" {
+        home_dir()
+    } else if let Some(rest) = path.strip_prefix("~/") {
+        home_dir().join(rest)
+    } else {
+        Path::new(path).to_path_buf()
+    }
+}
+
+fn deserialize_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|path| expand_path(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_music_dir_from_user_dirs() {
+        let home = Path::new("/home/user");
+        let contents = r#"
+# This file is written by xdg-user-dirs-update
+XDG_DESKTOP_DIR="$HOME/Desktop"
+XDG_MUSIC_DIR="$HOME/Audio/Music"
+"#;
+
+        assert_eq!(
+            parse_user_dir(contents, "XDG_MUSIC_DIR", home),
+            Some(PathBuf::from("/home/user/Audio/Music"))
+        );
+        assert_eq!(
+            parse_user_dir(r#"XDG_MUSIC_DIR="/mnt/music""#, "XDG_MUSIC_DIR", home),
+            Some(PathBuf::from("/mnt/music"))
+        );
+
+        for contents in [
+            r#"XDG_MUSIC_DIR="$HOME""#,
+            r#"XDG_MUSIC_DIR="Music""#,
+            r#"XDG_DESKTOP_DIR="$HOME/Desktop""#,
+        ] {
+            assert_eq!(
+                parse_user_dir(contents, "XDG_MUSIC_DIR", home),
+                None,
+                "{contents}"
+            );
+        }
+    }
+}
@@ -1,24 +1,30 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard};
 use std::time::Duration;
 
+use clap::Parser;
 use discord_presence::models::EventData;
-use discord_presence::models::{ActivityType, DisplayType};
+use discord_presence::models::{ActivityTimestamps, ActivityType, DisplayType};
 use discord_presence::{Client as DiscordClient, DiscordError};
+use mpd_client::Client as MpdClient;
 use mpd_client::client::ConnectionEvent::SubsystemChange;
 use mpd_client::client::Subsystem;
-use mpd_client::commands;
+use mpd_client::commands::{self, SongId};
 use mpd_client::responses::{PlayState, Song, SongInQueue, Status};
 use mpd_utils::MultiHostClient;
 use regex::Regex;
-use tokio::sync::mpsc;
-use tokio::time::sleep;
+use tokio::sync::{Mutex, mpsc};
+use tokio::time::{MissedTickBehavior, interval, sleep};
 use tracing::{debug, error, info};
 
-use crate::album_art::AlbumArtClient;
-use crate::config::DisplayType as ConfigDisplayType;
-use crate::mpd_conn::get_timestamp;
+use crate::album_art::{AlbumArtClient, blocking, pending};
+use crate::cli::{Cli, Command};
+use crate::config::{DisplayType as ConfigDisplayType, PendingQueueConfig};
+use crate::mpd_conn::{MusicDirectories, get_timestamp};
 use config::Config;
 
 mod album_art;
+mod cli;
 mod config;
 mod mpd_conn;
 
@@ -43,9 +49,18 @@ struct Tokens {
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let re = Regex::new(r"\$(\w+)").expect("Failed to parse regex");
-
+    let cli = Cli::parse();
     let config = Config::load();
+
+    if let Some(Command::Queue(args)) = cli.command {
+        if let Err(err) = cli::run_queue(args, &config) {
+            eprintln!("error: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let re = Regex::new(r"\$(\w+)").expect("Failed to parse regex");
     let format = &config.format;
 
     let tokens = Tokens {
@@ -59,6 +74,8 @@ async fn main() {
     let mut mpd = MultiHostClient::new(config.hosts.clone(), Duration::from_secs(IDLE_TIME));
     mpd.init();
 
+    let music_dirs = MusicDirectories::new(config.album_art.music_root.clone());
+
     let (tx, mut rx) = mpsc::channel(16);
     let mut service = Service::new(&config, tokens, tx);
     service.start();
@@ -70,18 +87,8 @@ async fn main() {
                     info!("Detected change, updating status");
                     debug!("Change: {event:?}");
 
-                    if let Ok((Some(status), current_song)) = mpd.with_client(|client| async move {
-                        let status = client.command(commands::Status).await.ok();
-
-                        let current_song = if status.is_some() {
-                            client.command(commands::CurrentSong).await.ok().flatten()
-                        } else {
-                            None
-                        };
-
-                        (status, current_song)
-                    }).await {
-                        service.update_state(&status, current_song).await;
+                    if let Some(player) = get_state(&mpd, &music_dirs).await {
+                        service.update_state(player).await;
                     }
                 }
             }
@@ -91,18 +98,8 @@ async fn main() {
                         info!("Connected to Discord");
 
                         // set initial status as soon as ready
-                        if let Ok((Some(status), current_song)) = mpd.with_client(|client| async move {
-                            let status = client.command(commands::Status).await.ok();
-
-                            let current_song = if status.is_some() {
-                                client.command(commands::CurrentSong).await.ok().flatten()
-                            } else {
-                                None
-                            };
-
-                            (status, current_song)
-                        }).await {
-                            service.update_state(&status, current_song).await;
+                        if let Some(player) = get_state(&mpd, &music_dirs).await {
+                            service.update_state(player).await;
                         }
                     },
                     ServiceEvent::Error(err) => {
@@ -116,14 +113,80 @@ async fn main() {
     }
 }
 
+/// The player state of the most relevant MPD server.
+struct PlayerState {
+    status: Status,
+    current_song: Option<SongInQueue>,
+    next_song: Option<SongInQueue>,
+    /// Directory the server's song URLs are relative to.
+    music_dir: PathBuf,
+    client: Arc<MpdClient>,
+}
+
+/// Fetches the player status, current and next song from the most relevant MPD server.
+async fn get_state(mpd: &MultiHostClient, music_dirs: &MusicDirectories) -> Option<PlayerState> {
+    mpd.with_client(|client| async move {
+        let status = client.command(commands::Status).await.ok()?;
+        let current_song = client.command(commands::CurrentSong).await.ok().flatten();
+
+        let next_song = match status.next_song {
+            Some((_, id)) => client
+                .command(commands::Queue::song(id))
+                .await
+                .ok()
+                .and_then(|songs| songs.into_iter().next()),
+            None => None,
+        };
+
+        let music_dir = music_dirs.resolve(&client).await;
+
+        Some(PlayerState {
+            status,
+            current_song,
+            next_song,
+            music_dir,
+            client,
+        })
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
 enum ServiceEvent {
     Ready,
     Error(String),
 }
 
+/// The contents of the activity, other than the album art.
+struct Presence {
+    details: String,
+    state: String,
+    large_text: String,
+    small_text: String,
+    display_type: DisplayType,
+    timestamps: ActivityTimestamps,
+    /// Shown when the album has no art, or while it is being looked up.
+    large_image: String,
+    small_image: String,
+}
+
+/// A song and its art's URL, or `None` if the album has no art.
+type ShownArt = (SongId, Option<String>);
+
 struct Service<'a> {
     config: &'a Config,
-    album_art_client: AlbumArtClient,
+    /// Shared with the tasks prefetching the next song's art.
+    album_art_client: Arc<Mutex<AlbumArtClient>>,
+    /// The song art was last prefetched for, to avoid fetching it again.
+    /// Prefetches for any other song are skipped, as it's no longer next.
+    prefetched_song: Arc<StdMutex<Option<SongId>>>,
+    /// The song art was last shown for, and its art,
+    /// so updates for the same song (eg seeking) show it again straight away.
+    shown_art: Arc<StdMutex<Option<ShownArt>>>,
+    /// Incremented each time the activity is changed,
+    /// so art found for an old song doesn't replace the current activity.
+    generation: Arc<StdMutex<u64>>,
     drpc: DiscordClient,
     tokens: Tokens,
 }
@@ -175,10 +238,24 @@ impl<'a> Service<'a> {
         })
         .persist();
 
-        let album_art_client = AlbumArtClient::new();
+        let album_art_client = Arc::new(Mutex::new(AlbumArtClient::new(
+            &config.album_art,
+            &config.pending_queue,
+        )));
+
+        if config.pending_queue.recheck_interval_secs > 0 {
+            tokio::spawn(recheck_pending_queue(
+                album_art_client.clone(),
+                config.pending_queue.clone(),
+            ));
+        }
+
         Self {
             config,
             album_art_client,
+            prefetched_song: Arc::new(StdMutex::new(None)),
+            shown_art: Arc::new(StdMutex::new(None)),
+            generation: Arc::new(StdMutex::new(0)),
             drpc,
             tokens,
         }
@@ -189,14 +266,19 @@ impl<'a> Service<'a> {
         self.drpc.start();
     }
 
-    async fn update_state(&mut self, status: &Status, current_song: Option<SongInQueue>) {
+    async fn update_state(&mut self, player: PlayerState) {
         // https://discord.com/developers/docs/rich-presence/how-to#updating-presence-update-presence-payload
         const MAX_BYTES: usize = 128;
 
         let format = &self.config.format;
+        let status = &player.status;
 
         if matches!(status.state, PlayState::Playing) {
-            if let Some(song_in_queue) = current_song {
+            let prefetch = player
+                .next_song
+                .and_then(|next_song| self.prefetch_album_art(next_song, player.music_dir.clone()));
+
+            if let Some(song_in_queue) = player.current_song {
                 let song = song_in_queue.song;
 
                 let mut details = clamp(
@@ -218,52 +300,306 @@ impl<'a> Service<'a> {
                     details.push('\u{200B}');
                 }
 
-                let timestamps = get_timestamp(status, format.timestamp);
-
-                let url = self.album_art_client.get_album_art_url(song).await;
-
-                let display_type = map_display_type(format.display_type);
-
-                let res = self.drpc.set_activity(|act| {
-                    act.state(state)
-                        .activity_type(ActivityType::Listening)
-                        .details(details)
-                        .status_display(display_type)
-                        .assets(|mut assets| {
-                            match url {
-                                Some(url) => assets = assets.large_image(url),
-                                None => {
-                                    if !format.large_image.is_empty() {
-                                        assets = assets.large_image(&format.large_image);
-                                    }
-                                }
-                            }
-
-                            if !format.small_image.is_empty() {
-                                assets = assets.small_image(&format.small_image);
-                            }
-                            if !large_text.is_empty() {
-                                assets = assets.large_text(large_text);
-                            }
-                            if !small_text.is_empty() {
-                                assets = assets.small_text(small_text);
-                            }
-                            assets
-                        })
-                        .timestamps(|_| timestamps)
-                });
-
-                if let Err(why) = res {
-                    // api returns a bogus error about missing buttons but succeeds anyway
-                    // so don't log it
-                    if !matches!(&why, DiscordError::JsonError(err) if err.to_string().starts_with("missing field `buttons`"))
-                    {
-                        error!("Failed to set activity: {why:?}");
-                    }
+                let presence = Presence {
+                    details,
+                    state,
+                    large_text,
+                    small_text,
+                    display_type: map_display_type(format.display_type),
+                    timestamps: get_timestamp(status, format.timestamp),
+                    large_image: format.large_image.clone(),
+                    small_image: format.small_image.clone(),
+                };
+
+                self.set_presence(
+                    song_in_queue.id,
+                    song,
+                    presence,
+                    player.music_dir.clone(),
+                    player.client.clone(),
+                    prefetch,
+                );
+            } else if let Some(prefetch) = prefetch {
+                tokio::spawn(prefetch);
+            }
+        } else {
+            let _generation = next_generation(&self.generation);
+
+            if let Err(why) = self.drpc.clear_activity() {
+                error!("Failed to clear activity: {why:?}");
+            }
+        }
+    }
+
+    /// Sets the activity for `song`.
+    ///
+    /// If the song's art is already known, it is shown straight away.
+    /// Otherwise the activity is set with the fallback image,
+    /// and set again once the art is found if nothing else has been shown since.
+    ///
+    /// `prefetch` is run once the song's art is known,
+    /// so looking up the next song's art can't hold up the current song's.
+    fn set_presence(
+        &mut self,
+        song_id: SongId,
+        song: Song,
+        presence: Presence,
+        music_dir: PathBuf,
+        client: Arc<MpdClient>,
+        prefetch: Option<impl Future<Output = ()> + Send + 'static>,
+    ) {
+        let shown = match &*self.shown_art.lock().expect("art lock not to be poisoned") {
+            Some((id, url)) if *id == song_id => Some(url.clone()),
+            _ => None,
+        };
+
+        // the client is locked while looking up art, possibly for another song,
+        // in which case the lookup below waits for it and answers from the cache
+        let cached = shown.clone().or_else(|| {
+            self.album_art_client
+                .try_lock()
+                .ok()
+                .and_then(|mut album_art_client| album_art_client.get_cached_album_art_url(&song))
+        });
+
+        let generation = {
+            let generation = next_generation(&self.generation);
+            set_activity(
+                &mut self.drpc,
+                &presence,
+                cached.clone().flatten().as_deref(),
+            );
+            *generation
+        };
+
+        if shown.is_some() {
+            if let Some(prefetch) = prefetch {
+                tokio::spawn(prefetch);
+            }
+            return;
+        }
+
+        if let Some(url) = cached {
+            *self.shown_art.lock().expect("art lock not to be poisoned") = Some((song_id, url));
+
+            // lookups record the song in the pending queue,
+            // which is skipped when the art is already known
+            let album_art_client = self.album_art_client.clone();
+            tokio::spawn(async move {
+                album_art_client
+                    .lock()
+                    .await
+                    .record_pending_track(&song, &music_dir)
+                    .await;
+            });
+
+            if let Some(prefetch) = prefetch {
+                tokio::spawn(prefetch);
+            }
+            return;
+        }
+
+        let album_art_client = self.album_art_client.clone();
+        let current_generation = self.generation.clone();
+        let shown_art = self.shown_art.clone();
+        let mut drpc = self.drpc.clone();
+
+        tokio::spawn(async move {
+            let is_current = || {
+                *current_generation
+                    .lock()
+                    .expect("generation lock not to be poisoned")
+                    == generation
+            };
+
+            let url = {
+                let mut album_art_client = album_art_client.lock().await;
+
+                // a newer update has its own lookup, which will answer from the cache
+                if is_current() {
+                    album_art_client
+                        .get_album_art_url(song, &music_dir, &client)
+                        .await
+                } else {
+                    debug!("Activity changed before its art was looked up, skipping lookup");
+                    None
                 }
+            };
+
+            if let Some(url) = url {
+                let current_generation = current_generation
+                    .lock()
+                    .expect("generation lock not to be poisoned");
+
+                if *current_generation == generation {
+                    set_activity(&mut drpc, &presence, Some(&url));
+                    *shown_art.lock().expect("art lock not to be poisoned") =
+                        Some((song_id, Some(url)));
+                } else {
+                    debug!("Song changed before its art was found, not updating activity");
+                }
+            }
+
+            if let Some(prefetch) = prefetch {
+                prefetch.await;
+            }
+        });
+    }
+
+    /// Gets a task looking up the art for the next song,
+    /// so it is already cached when the song starts.
+    ///
+    /// Returns `None` if the song's art has already been prefetched.
+    /// The task does nothing if another song has become next by the time it runs.
+    fn prefetch_album_art(
+        &mut self,
+        next_song: SongInQueue,
+        music_dir: PathBuf,
+    ) -> Option<impl Future<Output = ()> + Send + 'static> {
+        {
+            let mut prefetched_song = self
+                .prefetched_song
+                .lock()
+                .expect("prefetch lock not to be poisoned");
+
+            if *prefetched_song == Some(next_song.id) {
+                return None;
+            }
+
+            *prefetched_song = Some(next_song.id);
+        }
+
+        let album_art_client = self.album_art_client.clone();
+        let prefetched_song = self.prefetched_song.clone();
+
+        Some(async move {
+            let mut album_art_client = album_art_client.lock().await;
+
+            let is_next = *prefetched_song
+                .lock()
+                .expect("prefetch lock not to be poisoned")
+                == Some(next_song.id);
+
+            if is_next {
+                debug!("Prefetching album art for next song {:?}", next_song.id);
+                album_art_client
+                    .prefetch_album_art(&next_song.song, &music_dir)
+                    .await;
+            } else {
+                debug!(
+                    "Song {:?} is no longer next, skipping prefetch",
+                    next_song.id
+                );
+            }
+        })
+    }
+}
+
+/// Periodically checks whether releases in the pending queue
+/// have been added to MusicBrainz / Cover Art Archive, resolving any which have.
+///
+/// Releases are checked one at a time with a delay between each,
+/// so the checks don't hold up looking up art for the current song.
+async fn recheck_pending_queue(
+    album_art_client: Arc<Mutex<AlbumArtClient>>,
+    config: PendingQueueConfig,
+) {
+    let period = Duration::from_secs(config.recheck_interval_secs);
+    let delay = Duration::from_secs(config.recheck_delay_secs);
+
+    // the first pass runs shortly after starting rather than a full interval later,
+    // so the queue is still checked if the daemon is restarted more often than that
+    sleep(delay).await;
+
+    let mut ticks = interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+
+        let dir = config.dir.clone();
+        let entries = match blocking::run(move || pending::list_entries(&dir)).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("Failed to read pending queue: {err}");
+                continue;
+            }
+        };
+
+        debug!("Re-checking {} pending releases", entries.len());
+
+        let rechecker = album_art_client.lock().await.pending_rechecker();
+
+        let mut resolved = 0;
+        for entry in entries {
+            if let Some(cache_key) = rechecker.recheck(&entry).await {
+                album_art_client
+                    .lock()
+                    .await
+                    .remove_cached(&cache_key)
+                    .await;
+                resolved += 1;
             }
-        } else if let Err(why) = self.drpc.clear_activity() {
-            error!("Failed to clear activity: {why:?}");
+
+            sleep(delay).await;
+        }
+
+        if resolved > 0 {
+            info!("Resolved {resolved} pending releases");
+        }
+    }
+}
+
+/// Marks the start of a new activity, returning its generation.
+/// Art lookups for any earlier activity will no longer set it.
+///
+/// The lock is held until the returned guard is dropped,
+/// so the activity can be changed without racing an art lookup.
+fn next_generation(generation: &StdMutex<u64>) -> MutexGuard<'_, u64> {
+    let mut generation = generation
+        .lock()
+        .expect("generation lock not to be poisoned");
+    *generation += 1;
+    generation
+}
+
+/// Sets the activity, using `url` as the large image if set.
+fn set_activity(drpc: &mut DiscordClient, presence: &Presence, url: Option<&str>) {
+    let res = drpc.set_activity(|act| {
+        act.state(&presence.state)
+            .activity_type(ActivityType::Listening)
+            .details(&presence.details)
+            .status_display(presence.display_type.clone())
+            .assets(|mut assets| {
+                match url {
+                    Some(url) => assets = assets.large_image(url),
+                    None => {
+                        if !presence.large_image.is_empty() {
+                            assets = assets.large_image(&presence.large_image);
+                        }
+                    }
+                }
+
+                if !presence.small_image.is_empty() {
+                    assets = assets.small_image(&presence.small_image);
+                }
+                if !presence.large_text.is_empty() {
+                    assets = assets.large_text(&presence.large_text);
+                }
+                if !presence.small_text.is_empty() {
+                    assets = assets.small_text(&presence.small_text);
+                }
+                assets
+            })
+            .timestamps(|_| presence.timestamps.clone())
+    });
+
+    if let Err(why) = res {
+        // api returns a bogus error about missing buttons but succeeds anyway
+        // so don't log it
+        if !matches!(&why, DiscordError::JsonError(err) if err.to_string().starts_with("missing field `buttons`"))
+        {
+            error!("Failed to set activity: {why:?}");
         }
     }
 }
@@ -1,8 +1,14 @@
 use crate::config::TimestampMode;
 use discord_presence::models::ActivityTimestamps;
+use mpd_client::Client;
+use mpd_client::client::CommandError;
+use mpd_client::protocol::Command as RawCommand;
 use mpd_client::responses::{Song, Status};
 use mpd_client::tag::Tag;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
 
 /// Formats a duration given in seconds
 /// in hh:mm format
@@ -83,3 +89,77 @@ fn get_duration(status: &Status) -> Option<u64> {
 fn get_elapsed(status: &Status) -> Option<u64> {
     status.elapsed.map(|e| e.as_secs())
 }
+
+/// Caches the music directory of each connected MPD server,
+/// as reported by its `config` command.
+///
+/// MPD only answers `config` for clients connected over a local socket,
+/// so servers that refuse fall back to the configured music root.
+pub struct MusicDirectories {
+    fallback: PathBuf,
+    /// Keyed by connection. Holding a weak reference keeps the allocation alive,
+    /// so a pointer can never be reused by a different client while it is cached.
+    entries: Mutex<Vec<(Weak<Client>, Option<PathBuf>)>>,
+}
+
+impl MusicDirectories {
+    pub fn new(fallback: PathBuf) -> Self {
+        Self {
+            fallback,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Gets the music directory for the server `client` is connected to,
+    /// querying MPD the first time a connection is seen.
+    pub async fn resolve(&self, client: &Arc<Client>) -> PathBuf {
+        if let Some(dir) = self.get_cached(client) {
+            return dir.unwrap_or_else(|| self.fallback.clone());
+        }
+
+        let dir = match client.raw_command(RawCommand::new("config")).await {
+            Ok(frame) => {
+                let dir = frame.find("music_directory").map(PathBuf::from);
+                debug!("MPD reported music directory {dir:?}");
+                Some(dir)
+            }
+            Err(CommandError::ErrorResponse { error, .. }) => {
+                debug!(
+                    "MPD refused to report its music directory ({}), using configured root",
+                    error.message
+                );
+                Some(None)
+            }
+            Err(err) => {
+                warn!("Failed to query MPD music directory: {err}");
+                None
+            }
+        };
+
+        match dir {
+            Some(dir) => {
+                self.entries
+                    .lock()
+                    .expect("Failed to get lock on music directories")
+                    .push((Arc::downgrade(client), dir.clone()));
+                dir.unwrap_or_else(|| self.fallback.clone())
+            }
+            None => self.fallback.clone(),
+        }
+    }
+
+    fn get_cached(&self, client: &Arc<Client>) -> Option<Option<PathBuf>> {
+        let mut entries = self
+            .entries
+            .lock()
+            .expect("Failed to get lock on music directories");
+
+        // drop connections which have since been closed
+        entries.retain(|(weak, _)| weak.strong_count() > 0);
+
+        entries
+            .iter()
+            .find(|(weak, _)| Weak::ptr_eq(weak, &Arc::downgrade(client)))
+            .map(|(_, dir)| dir.clone())
+    }
+}